@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use gltf::buffer::Data;
+use gltf::Document;
+use rapier3d::prelude::*;
+
+use crate::gltf_ext::GltfExt;
+use crate::lobby::Asset;
+use crate::physics::{ColliderFallback, PhysicsProperties};
+
+// Whether the colliders were generated because the model defines none
+pub type Colliders = Arc<(Vec<(ColliderBuilder, PhysicsProperties)>, bool)>;
+
+// Colliders for the models shipped with the server, shared between every lobby. Builders hold their shape
+// in a `SharedShape`, so identical pieces reuse the same geometry. Uploaded meshes cache theirs alongside
+// the asset contents instead (see `AssetData`), so they're freed once no lobby uses the asset.
+static COLLIDER_CACHE: LazyLock<Mutex<HashMap<(PathBuf, ColliderFallback), Colliders>>> = LazyLock::new(Default::default);
+
+// Builds colliders for pawn meshes, caching them so repeated spawns and mesh swaps skip GLTF parsing
+pub struct ColliderFactory;
+impl ColliderFactory {
+    pub fn colliders(mesh: &str, assets: &HashMap<String, Asset>, fallback: ColliderFallback)
+        -> Result<(Vec<(ColliderBuilder, PhysicsProperties)>, bool), Box<dyn Error>> {
+        let static_path = Path::new("./static/games").canonicalize()?;
+        let path = static_path.join(Path::new(mesh)).canonicalize();
+
+        if let Ok(path) = path {
+            if !path.starts_with(&static_path) { return Ok((vec![], false)); }
+            let key = (path, fallback);
+            if let Some(colliders) = COLLIDER_CACHE.lock().unwrap().get(&key) {
+                return Ok(colliders.as_ref().clone());
+            }
+
+            // Parse without holding the cache lock, other lobbies may be spawning too
+            let (gltf_document, gltf_buffers, _) = gltf::import(&key.0)?;
+            let colliders = Self::build(&gltf_document, &gltf_buffers, fallback)?;
+            COLLIDER_CACHE.lock().unwrap().insert(key, colliders.clone());
+            Ok(colliders.as_ref().clone())
+        } else if let Some(asset) = assets.get(&format!("/{}", mesh)) {
+            if let Some(colliders) = asset.data.colliders.lock().unwrap().get(&fallback) {
+                return Ok(colliders.as_ref().clone());
+            }
+
+            let (gltf_document, gltf_buffers, _) = gltf::import_slice(&asset.data[..])?;
+            let colliders = Self::build(&gltf_document, &gltf_buffers, fallback)?;
+            asset.data.colliders.lock().unwrap().insert(fallback, colliders.clone());
+            Ok(colliders.as_ref().clone())
+        } else {
+            Ok((vec![], false))
+        }
+    }
+    fn build(gltf_document: &Document, gltf_buffers: &[Data], fallback: ColliderFallback) -> Result<Colliders, Box<dyn Error>> {
+        let mut colliders: Vec<_> = gltf_document.colliders(gltf_buffers)?.collect();
+        let generated = colliders.is_empty();
+        if generated {
+            colliders.extend(gltf_document.fallback_collider(gltf_buffers, fallback)
+                .map(|collider| (collider, PhysicsProperties::default())));
+        }
+        Ok(Arc::new((colliders, generated)))
+    }
+}
@@ -5,7 +5,8 @@ use serde::{Serialize, Deserialize};
 
 use crate::user::{User, UserId};
 use crate::pawn::{Pawn, PawnUpdate, PawnId};
-use crate::math::Vec3;
+use crate::joint::{Joint, JointId};
+use crate::math::{Quat, Vec3};
 use crate::lobby::{GameInfo, LobbySettings};
 use crate::physics::CollisionAudioInfo;
 
@@ -31,8 +32,9 @@ pub enum Event<'a> {
     Join { referrer: &'a str },
     #[serde(skip_deserializing)]
     Start {
-        id: UserId, host: UserId, color: &'a str, info: &'a Option<GameInfo>, settings: &'a LobbySettings,
-        users: Vec<&'a User>, pawns: Vec<&'a Pawn>, registered_pawns: &'a IndexMap<String, Vec<Pawn>>
+        id: UserId, host: UserId, color: &'a str, token: &'a str, info: &'a Option<GameInfo>, assets: HashMap<String, String>, settings: &'a LobbySettings,
+        users: Vec<&'a User>, pawns: Vec<Cow<'a, Pawn>>, joints: Vec<&'a Joint>,
+        registered_pawns: &'a IndexMap<String, Vec<Pawn>>
     },
     AssignHost { id: UserId },
     #[serde(skip_deserializing)]
@@ -40,7 +42,8 @@ pub enum Event<'a> {
     Disconnect { id: UserId },
     Settings(Cow<'a, LobbySettings>),
 
-    RegisterGame { info: Cow<'a, GameInfo>, assets: HashMap<String, String> },
+    RegisterGame { info: Cow<'a, GameInfo>, assets: HashMap<String, String> }, // Data URLs from the host, versions back from the server
+    RegisterPlugin { id: &'a str }, // Start a plugin from the server's library
     RegisterPawn { path: &'a str, pawn: Cow<'a, Pawn> },
 
     Ping { idx: u64 },
@@ -57,8 +60,25 @@ pub enum Event<'a> {
         updates: Vec<PawnUpdate>,
         #[serde(skip_serializing_if = "Option::is_none")]
         collisions: Option<Vec<CollisionAudioInfo>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tick: Option<u64>, // Server physics tick the updates were sent on
+    },
+    // Group selection, moved rigidly about `origin`
+    GrabGroup { ids: Vec<PawnId>, origin: Option<Vec3> },
+    MoveGroup { position: Vec3, rotation: Quat },
+    ReleaseGroup {},
+    AttachPawns { joint: Cow<'a, Joint> },
+    DetachPawns {
+        #[serde(rename = "joints")]
+        ids: Vec<JointId>
     },
     AddPawnToHand { pawn: Cow<'a, Pawn> },
+    #[serde(skip_deserializing)]
+    RemovePawnsFromHand {
+        #[serde(rename = "pawns")]
+        ids: Vec<PawnId>
+    },
+    ReorderHand { from_id: UserId, order: Vec<PawnId> },
     HandCount { id: UserId, count: u64 },
 
     // 'Extracting' a pawn and 'taking' a pawn are different
@@ -67,6 +87,13 @@ pub enum Event<'a> {
     StorePawn { from_id: PawnId, into_id: PawnOrUser },
     TakePawn { from_id: UserId, target_id: PawnId, position_hint: Option<Vec3> },
 
+    // Hand management, `to_id: None` reveals to everyone
+    RevealPawn { from_id: UserId, target_id: PawnId, to_id: Option<UserId> },
+    #[serde(skip_deserializing)]
+    ShowPawn { from_id: UserId, pawn: Cow<'a, Pawn> },
+    PassPawn { from_id: UserId, target_id: PawnId, to_id: UserId },
+    DiscardPawn { from_id: UserId, target_id: PawnId, into_id: PawnId },
+
     UpdateUserStatuses { updates: Vec<UserStatusUpdate> },
 
     Chat { id: Option<UserId>, content: Cow<'a, String> },
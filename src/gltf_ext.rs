@@ -1,68 +1,162 @@
-use gltf::{buffer::Data, mesh::BoundingBox, Document, Gltf};
-use rapier3d::{math::Point, na::Quaternion, prelude::{ColliderBuilder, Isometry, Rotation, Translation, Vector}};
+use gltf::{buffer::Data, mesh::BoundingBox, scene::Transform, Document, Mesh, Node};
+use rapier3d::{math::Point, na::{Matrix4, Quaternion}, prelude::{ColliderBuilder, Isometry, Rotation, Translation, Vector}};
+use std::error::Error;
 use std::vec::IntoIter;
 
+use crate::physics::{ColliderFallback, PhysicsProperties};
+
 fn merge_bounding_boxes(mut a: BoundingBox, b: BoundingBox) -> BoundingBox {
     a.min = [a.min[0].min(b.min[0]), a.min[1].min(b.min[1]), a.min[2].min(b.min[2])];
     a.max = [a.max[0].max(b.max[0]), a.max[1].max(b.max[1]), a.max[2].max(b.max[2])];
     a
 }
 
+// Every node along with its world transform, walking down from the scene roots
+fn world_transforms<'a>(node: Node<'a>, parent: &Matrix4<f32>, out: &mut Vec<(Node<'a>, Matrix4<f32>)>) {
+    let transform = parent * Matrix4::from(node.transform().matrix());
+    for child in node.children() {
+        world_transforms(child, &transform, out);
+    }
+    out.push((node, transform));
+}
+
+// Nodes of the default (or first) scene
+fn scene_nodes(document: &Document) -> Vec<(Node<'_>, Matrix4<f32>)> {
+    let mut nodes = vec![];
+    for scene in document.default_scene().into_iter().chain(document.scenes()).take(1) {
+        for node in scene.nodes() {
+            world_transforms(node, &Matrix4::identity(), &mut nodes);
+        }
+    }
+    nodes
+}
+
+// Vertices of every primitive in `mesh` (scaled), and their triangles
+fn mesh_geometry(mesh: &Mesh, buffers: &[Data], scale: &Vector<f32>) -> (Vec<Point<f32>>, Vec<[u32; 3]>) {
+    let mut points: Vec<Point<f32>> = vec![];
+    let mut indices: Vec<[u32; 3]> = vec![];
+    for primitive in mesh.primitives() {
+        let reader = primitive.reader(|buffer: gltf::Buffer| Some(&buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else { continue; };
+
+        let offset = points.len() as u32;
+        points.extend(positions.map(|p| Point::new(p[0] * scale.x, p[1] * scale.y, p[2] * scale.z)));
+        if let Some(read_indices) = reader.read_indices() {
+            let flat: Vec<u32> = read_indices.into_u32().collect();
+            indices.extend(flat.chunks_exact(3).map(|t| [t[0] + offset, t[1] + offset, t[2] + offset]));
+        } else { // Non-indexed primitives list their triangles in order
+            let count = points.len() as u32 - offset;
+            indices.extend((0..count/3).map(|i| [offset + i*3, offset + i*3 + 1, offset + i*3 + 2]));
+        }
+    }
+    (points, indices)
+}
+
 pub trait GltfExt {
-    fn colliders(&self, buffers: &[Data]) -> IntoIter<ColliderBuilder>;
+    /// Colliders along with any physical properties set in their node's extras
+    fn colliders(&self, buffers: &[Data]) -> Result<IntoIter<(ColliderBuilder, PhysicsProperties)>, Box<dyn Error>>;
+    /// A collider generated from every mesh, for models without collider extras
+    fn fallback_collider(&self, buffers: &[Data], mode: ColliderFallback) -> Option<ColliderBuilder>;
 }
 impl GltfExt for Document {
-    fn colliders(&self, buffers: &[Data]) -> IntoIter<ColliderBuilder> {
-        let colliders: Vec<_> = self
-            .nodes()
-            .filter_map(|node| {
-                let extras: serde_json::Value = serde_json::from_str(node.extras().as_ref()?.get()).ok()?;
+    fn colliders(&self, buffers: &[Data]) -> Result<IntoIter<(ColliderBuilder, PhysicsProperties)>, Box<dyn Error>> {
+        let mut colliders = vec![];
+        for (node, transform) in scene_nodes(self) {
+            let Some(extras) = node.extras().as_ref()
+                .and_then(|extras| serde_json::from_str::<serde_json::Value>(extras.get()).ok()) else { continue; };
+            let Some(collider) = extras.get("collider").and_then(|c| c.as_str()) else { continue; };
+            let node_name = node.name().unwrap_or("unnamed").to_string();
+            let properties: PhysicsProperties = serde_json::from_value(extras.clone())
+                .map_err(|e| format!("Invalid physical properties on collider node `{}`: {}", node_name, e))?;
+
+            let mesh = node.mesh().ok_or(format!("Collider node `{}` has no mesh", node_name))?;
 
-                let collider = extras.get("collider")?.as_str()?;
+            let bounds = mesh.primitives().map(|p| p.bounding_box()).fold(BoundingBox {
+                min: [f32::MAX,f32::MAX,f32::MAX], max: [f32::MIN,f32::MIN,f32::MIN]
+            }, |acc, b| merge_bounding_boxes(acc, b));
+            let transform = Transform::Matrix { matrix: transform.into() }.decomposed();
 
-                let bounding_boxes: Vec<_> = node.mesh()?.primitives().map(|p| p.bounding_box()).collect();
-                let bounds = bounding_boxes.into_iter().fold(BoundingBox {
-                    min: [f32::MAX,f32::MAX,f32::MAX], max: [f32::MIN,f32::MIN,f32::MIN]
-                }, |acc, b| merge_bounding_boxes(acc, b));
-                let transform = node.transform().decomposed();
+            let min = Vector::new(bounds.min[0], bounds.min[1], bounds.min[2]);
+            let max = Vector::new(bounds.max[0], bounds.max[1], bounds.max[2]);
+            let scale = Vector::new(transform.2[0], transform.2[1], transform.2[2]);
+            let half = ((max - min)/2.).component_mul(&scale);
+            let center = ((max + min)/2.).component_mul(&scale);
 
-                let min = Vector::new(bounds.min[0], bounds.min[1], bounds.min[2]);
-                let max = Vector::new(bounds.max[0], bounds.max[1], bounds.max[2]);
-                let scale = Vector::new(transform.2[0], transform.2[1], transform.2[2]);
-                let half = ((max - min)/2.).component_mul(&scale);
-                let center = ((max + min)/2.).component_mul(&scale);
-                
-                // First translate center (of bounds) -> then rotation -> then translate node
-                // FIXME: This whole thing probably doesn't support nested transformations!
-                let rotation = Rotation::from_quaternion(
-                    Quaternion::new(transform.1[3], transform.1[0], transform.1[1], transform.1[2])
-                );
-                let translation = Translation::from(
-                    Vector::new(transform.0[0], transform.0[1], transform.0[2])
-                );
-                let isometry = Isometry::from_parts(translation, rotation) * Isometry::from(center);
+            // First translate center (of bounds) -> then rotation -> then translate node
+            let rotation = Rotation::from_quaternion(
+                Quaternion::new(transform.1[3], transform.1[0], transform.1[1], transform.1[2])
+            );
+            let translation = Translation::from(
+                Vector::new(transform.0[0], transform.0[1], transform.0[2])
+            );
+            let node_isometry = Isometry::from_parts(translation, rotation);
+            let isometry = node_isometry * Isometry::from(center);
 
-                let collider = match collider {
-                    "cylinder" => {
-                        ColliderBuilder::cylinder(half.y, half.x).position(isometry)
-                    },
-                    "box" => {
-                        ColliderBuilder::cuboid(half.x, half.y, half.z).position(isometry)
-                    },
-                    "convex" => {
-                        let primitive = node.mesh()?.primitives().next()?;
-                        let reader = primitive.reader(|buffer: gltf::Buffer| Some(&buffers[buffer.index()]));
-                        let points: Vec<Point<f32>> = reader.read_positions()?.map(|p| Point::new(p[0], p[1], p[2])).collect();
-                        ColliderBuilder::convex_hull(points.as_slice())?.position(isometry)
-                    },
-                    _ => {
-                        ColliderBuilder::ball(1.0)
+            let collider = match collider {
+                "box" => ColliderBuilder::cuboid(half.x, half.y, half.z).position(isometry),
+                "cylinder" => ColliderBuilder::cylinder(half.y, half.x).position(isometry),
+                "sphere" => ColliderBuilder::ball(half.x.max(half.y).max(half.z)).position(isometry),
+                "capsule" => {
+                    let radius = half.x.max(half.z);
+                    ColliderBuilder::capsule_y((half.y - radius).max(0.), radius).position(isometry)
+                },
+                "cone" => ColliderBuilder::cone(half.y, half.x).position(isometry),
+                "convex" | "trimesh" | "convex_decomposition" => {
+                    // Vertices are already in node space, so skip the bounds center
+                    let (points, indices) = mesh_geometry(&mesh, buffers, &scale);
+                    if indices.is_empty() {
+                        return Err(format!("Collider node `{}` has no triangles", node_name).into());
                     }
-                };
+                    match collider {
+                        "convex" => ColliderBuilder::convex_hull(points.as_slice())
+                            .ok_or(format!("Failed to build convex hull for node `{}`", node_name))?,
+                        "trimesh" => ColliderBuilder::trimesh(points, indices), // Only for immovable pawns
+                        _ => ColliderBuilder::convex_decomposition(points.as_slice(), indices.as_slice()),
+                    }.position(node_isometry)
+                },
+                other => {
+                    return Err(format!(
+                        "Unknown collider type `{}` on node `{}`, expected one of: box, cylinder, sphere, capsule, cone, convex, convex_decomposition, trimesh",
+                        other, node_name
+                    ).into());
+                }
+            };
 
-                Some(collider)
-            }).collect();
+            colliders.push((collider, properties));
+        }
 
-        colliders.into_iter()
+        Ok(colliders.into_iter())
     }
-}
\ No newline at end of file
+    fn fallback_collider(&self, buffers: &[Data], mode: ColliderFallback) -> Option<ColliderBuilder> {
+        let mut points: Vec<Point<f32>> = vec![];
+        for (node, transform) in scene_nodes(self) {
+            let Some(mesh) = node.mesh() else { continue; };
+            match mode {
+                ColliderFallback::None => return None,
+                ColliderFallback::Box => { // Corners of every primitive's bounds
+                    for bounds in mesh.primitives().map(|p| p.bounding_box()) {
+                        for i in 0..8 {
+                            let corner = |axis: usize| if i & (1 << axis) == 0 { bounds.min[axis] } else { bounds.max[axis] };
+                            points.push(transform.transform_point(&Point::new(corner(0), corner(1), corner(2))));
+                        }
+                    }
+                },
+                ColliderFallback::Hull => {
+                    let (vertices, _) = mesh_geometry(&mesh, buffers, &Vector::new(1., 1., 1.));
+                    points.extend(vertices.iter().map(|p| transform.transform_point(p)));
+                },
+            }
+        }
+        if points.is_empty() { return None; }
+
+        match mode {
+            ColliderFallback::Hull => ColliderBuilder::convex_hull(points.as_slice()),
+            _ => {
+                let min = points.iter().fold(Vector::repeat(f32::MAX), |acc, p| acc.inf(&p.coords));
+                let max = points.iter().fold(Vector::repeat(f32::MIN), |acc, p| acc.sup(&p.coords));
+                let half = (max - min)/2.;
+                Some(ColliderBuilder::cuboid(half.x, half.y, half.z).translation((max + min)/2.))
+            }
+        }
+    }
+}
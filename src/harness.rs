@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::Read;
+use std::path::Path;
+
+use axum::extract::ws::Message;
+use axum::http::HeaderMap;
+use flate2::{Decompress, read::ZlibDecoder};
+use indexmap::IndexMap;
+use tokio::sync::mpsc;
+
+use crate::events::Event;
+use crate::lobby::Lobby;
+use crate::plugin::Plugin;
+use crate::pawn::{Pawn, PawnId};
+use crate::user::UserId;
+
+// A user without a browser, holding everything the server has sent them
+pub struct VirtualUser {
+    pub id: UserId,
+    rx: mpsc::UnboundedReceiver<Message>,
+    received: Vec<serde_json::Value>,
+}
+impl VirtualUser {
+    fn receive(&mut self) {
+        while let Ok(message) = self.rx.try_recv() {
+            let Message::Binary(bytes) = message else { continue; };
+
+            let mut d = Decompress::new(false);
+            d.set_dictionary(include_bytes!("dictionary.txt")).expect("Failed to set DEFLATE dictionary");
+            let mut message_text = String::new();
+            ZlibDecoder::new_with_decompress(bytes.as_slice(), d).read_to_string(&mut message_text)
+                .expect("Server sent a malformed message");
+            self.received.push(serde_json::from_str(&message_text).expect("Server sent malformed JSON"));
+        }
+    }
+}
+
+/// An in-process lobby for running plugins headlessly, e.g. from tests.
+/// Events go through the same dispatch as websocket messages, and physics only advances through `step`.
+pub struct Harness {
+    pub lobby: Lobby,
+    users: IndexMap<UserId, VirtualUser>,
+}
+impl Harness {
+    pub fn new() -> Harness {
+        let mut lobby = Lobby::new();
+        lobby.name = "harness".to_string();
+        Harness { lobby, users: IndexMap::new() }
+    }
+
+    /// Connect and join a new user, the first one hosts
+    pub fn connect(&mut self) -> Result<UserId, Box<dyn Error>> {
+        let (tx, rx) = mpsc::unbounded_channel::<Message>();
+        let id = self.lobby.add_user(tx);
+        if self.users.is_empty() { self.lobby.host = id; }
+        self.users.insert(id, VirtualUser { id, rx, received: vec![] });
+
+        self.send(id, Event::Join { referrer: "" })?;
+        Ok(id)
+    }
+    pub fn send(&mut self, user: UserId, event: Event<'_>) -> Result<(), Box<dyn Error>> {
+        self.lobby.handle_event(user, event, &HeaderMap::new())
+    }
+    /// Send an event as a client would, e.g. `json!({ "type": "chat", "content": "hi" })`
+    pub fn send_json(&mut self, user: UserId, event: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        let message_text = event.to_string();
+        self.send(user, serde_json::from_str(&message_text)?)
+    }
+    /// Register a plugin folder (like `plugins/chess`), uploading the assets the plugin loader would
+    pub fn load_plugin(&mut self, user: UserId, folder: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        self.register_plugin(user, &Plugin::from_folder(folder)?)
+    }
+    pub fn register_plugin(&mut self, user: UserId, plugin: &Plugin) -> Result<(), Box<dyn Error>> {
+        self.send(user, Event::RegisterGame { info: Cow::Borrowed(&plugin.info), assets: plugin.data_urls() })
+    }
+    /// Advance physics, timers and `game.physics` by `ticks`
+    pub fn step(&mut self, ticks: u64) -> Result<(), Box<dyn Error>> {
+        for _ in 0..ticks {
+            self.lobby.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn pawns(&self) -> impl Iterator<Item = &Pawn> {
+        self.lobby.pawns.values()
+    }
+    pub fn pawns_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Pawn> {
+        self.pawns().filter(move |p| p.name.as_deref() == Some(name))
+    }
+    pub fn hand(&self, user: UserId) -> &IndexMap<PawnId, Pawn> {
+        &self.lobby.users[&user].hand
+    }
+    /// Every event sent to `user` so far, as JSON
+    pub fn received(&mut self, user: UserId) -> &[serde_json::Value] {
+        let user = self.users.get_mut(&user).expect("Harness missing user");
+        user.receive();
+        &user.received
+    }
+    /// Every chat message sent to `user` so far, including system messages
+    pub fn chat(&mut self, user: UserId) -> Vec<String> {
+        self.received(user).iter()
+            .filter(|event| event["type"] == "chat")
+            .filter_map(|event| event["content"].as_str().map(str::to_string))
+            .collect()
+    }
+}
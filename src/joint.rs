@@ -0,0 +1,81 @@
+use serde::{Serialize, Deserialize};
+use rapier3d::prelude::*;
+
+use crate::math::*;
+use crate::pawn::PawnId;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
+pub struct JointId(pub u64);
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JointKind {
+    Fixed,
+    // Axis is in world space, limits are in radians
+    Revolute { axis: Vec3, limits: Option<Vec2> },
+    // Axis is in world space, limits are in world units
+    Prismatic { axis: Vec3, limits: Option<Vec2> },
+}
+
+// Attaches two pawns in their current relative pose
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Joint {
+    #[serde(default)]
+    pub id: JointId, // Assigned by the server
+    pub pawns: (PawnId, PawnId),
+    #[serde(flatten)]
+    pub kind: JointKind,
+    pub anchor: Option<Vec3>, // World space, defaults to the first pawn's position
+
+    #[serde(skip)]
+    pub handle: Option<ImpulseJointHandle>,
+}
+impl Joint {
+    pub fn build(&self, pose1: &Isometry<f32>, pose2: &Isometry<f32>) -> GenericJoint {
+        let (mask, axis, limits) = match &self.kind {
+            JointKind::Fixed => (JointAxesMask::LOCKED_FIXED_AXES, None, None),
+            JointKind::Revolute { axis, limits } => (JointAxesMask::LOCKED_REVOLUTE_AXES, Some(axis), limits.map(|l| (JointAxis::AngX, l))),
+            JointKind::Prismatic { axis, limits } => (JointAxesMask::LOCKED_PRISMATIC_AXES, Some(axis), limits.map(|l| (JointAxis::LinX, l))),
+        };
+
+        // Joint frame in world space, with the free axis along X
+        let anchor = self.anchor.as_ref().map_or(pose1.translation.vector, Vector::from);
+        let rotation = axis.and_then(|axis| Rotation::rotation_between(&Vector::x(), &Vector::from(axis)))
+            .unwrap_or(Rotation::identity());
+        let frame = Isometry::from_parts(anchor.into(), rotation);
+
+        let mut builder = GenericJointBuilder::new(mask)
+            .local_frame1(pose1.inverse() * frame)
+            .local_frame2(pose2.inverse() * frame)
+            .contacts_enabled(false);
+        if let Some((joint_axis, limits)) = limits {
+            builder = builder.limits(joint_axis, [limits.x as f32, limits.y as f32]);
+        }
+        builder.build()
+    }
+}
+
+impl<'lua> mlua::FromLua<'lua> for Joint {
+    fn from_lua(value: mlua::Value<'lua>, _: &'lua mlua::Lua) -> mlua::Result<Self> {
+        if let mlua::Value::Table(params) = value {
+            let axis = params.get::<_, Option<Vec3>>("axis")?.unwrap_or(Vec3 { x: 0., y: 1., z: 0. });
+            let limits = params.get::<_, Option<Vec2>>("limits")?;
+            Ok(Joint {
+                id: JointId(0),
+                pawns: (PawnId(params.get::<_, mlua::Table>("pawns")?.get(1)?),
+                        PawnId(params.get::<_, mlua::Table>("pawns")?.get(2)?)),
+                kind: match params.get::<_, Option<String>>("kind")?.as_deref() {
+                    None | Some("fixed") => JointKind::Fixed,
+                    Some("revolute") => JointKind::Revolute { axis, limits },
+                    Some("prismatic") => JointKind::Prismatic { axis, limits },
+                    Some(kind) => return Err(mlua::Error::RuntimeError(format!("Unknown joint kind `{}`", kind))),
+                },
+                anchor: params.get("anchor")?,
+
+                handle: None,
+            })
+        } else {
+            Err(mlua::Error::FromLuaConversionError { from: "table", to: "Joint", message: None })
+        }
+    }
+}
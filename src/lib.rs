@@ -0,0 +1,23 @@
+pub mod math;
+pub mod pawn;
+pub mod zone;
+pub mod joint;
+pub mod lobby;
+pub mod user;
+pub mod physics;
+pub mod events;
+pub mod gltf_ext;
+pub mod colliders;
+pub mod server;
+pub mod plugin;
+pub mod library;
+pub mod harness;
+
+pub use lobby::Lobby;
+pub use pawn::Pawn;
+pub use events::Event;
+pub use physics::PhysicsWorld;
+pub use library::PluginLibrary;
+pub use server::{Lobbies, Server, ServerHooks};
+
+pub const PHYSICS_RATE: f32 = 1.0/45.0;
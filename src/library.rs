@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use zip::ZipArchive;
+
+use crate::lobby::GameInfo;
+use crate::plugin::Plugin;
+
+#[derive(Clone, Serialize, Debug)]
+pub struct CatalogEntry {
+    pub id: String,
+    #[serde(flatten)]
+    pub info: GameInfo,
+}
+
+/// Plugins installed on the server, packed (`plugins/<id>.zip`) or unpacked (`plugins/<id>/manifest.json`),
+/// which hosts can start by id instead of uploading every asset
+#[derive(Default)]
+pub struct PluginLibrary {
+    plugins: BTreeMap<String, (GameInfo, PathBuf)>,
+}
+impl PluginLibrary {
+    /// Index every zip and plugin folder in `folder` by its manifest, skipping ones that can't be read.
+    /// A zip wins over a folder of the same name, as that's what `plugin <folder>` packs
+    pub fn scan(folder: impl AsRef<Path>) -> PluginLibrary {
+        let mut plugins = BTreeMap::new();
+        let Ok(entries) = fs::read_dir(folder.as_ref()) else {
+            println!("Plugin folder {:?} missing, library is empty", folder.as_ref());
+            return PluginLibrary { plugins };
+        };
+
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let packed = path.extension() == Some("zip".as_ref());
+            if !packed && !path.join("manifest.json").is_file() { continue; }
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()) else { continue; };
+
+            let manifest = if packed { read_manifest(&path) } else { read_folder_manifest(&path) };
+            match manifest {
+                Ok(info) => {
+                    if packed || !plugins.contains_key(&id) { plugins.insert(id, (info, path)); }
+                },
+                Err(e) => println!("Skipping plugin {:?}: {}", path, e),
+            }
+        }
+        println!("Plugin library has {} plugins", plugins.len());
+        PluginLibrary { plugins }
+    }
+
+    pub fn catalog(&self) -> Vec<CatalogEntry> {
+        self.plugins.iter()
+            .map(|(id, (info, _))| CatalogEntry { id: id.clone(), info: info.clone() })
+            .collect()
+    }
+    /// Read an installed plugin's assets from disk
+    pub fn load(&self, id: &str) -> Result<Plugin, Box<dyn Error>> {
+        let (_, path) = self.plugins.get(id).ok_or(format!("No plugin named \"{}\"", id))?;
+        if path.is_dir() {
+            Plugin::from_folder(path)
+        } else {
+            Plugin::from_zip(File::open(path)?)
+        }
+    }
+}
+
+// Only the manifest, so scanning doesn't inflate every asset
+fn read_manifest(path: &Path) -> Result<GameInfo, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let manifest = archive.by_name("manifest.json")
+        .map_err(|e| format!("Failed to read manifest.json: {}", e))?;
+    Ok(serde_json::from_reader(manifest).map_err(|e| format!("Invalid manifest.json: {}", e))?)
+}
+fn read_folder_manifest(path: &Path) -> Result<GameInfo, Box<dyn Error>> {
+    let manifest = fs::read_to_string(path.join("manifest.json"))
+        .map_err(|e| format!("Failed to read manifest.json: {}", e))?;
+    Ok(serde_json::from_str(&manifest).map_err(|e| format!("Invalid manifest.json: {}", e))?)
+}
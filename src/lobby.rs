@@ -1,40 +1,116 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::atomic::{Ordering, AtomicU64};
 use std::error::Error;
-use std::sync::Arc;
+use std::f64::consts::FRAC_PI_2;
+use std::ops::Deref;
+use std::sync::{Arc, LazyLock, Mutex, Weak};
 use std::time::SystemTime;
 use data_url::DataUrl;
-use gltf::buffer::Data;
 use indexmap::IndexMap;
+use rand::seq::SliceRandom;
 use random_color::Color;
+use tokio::sync::{mpsc, Notify};
 use tokio::time::Instant;
 use include_dir::{Dir, include_dir};
+use axum::extract::ws::Message;
+use axum::http::{header, HeaderMap};
 
-use gltf::{Document, Gltf};
 use rapier3d::prelude::*;
 use rapier3d::dynamics::{RigidBodyBuilder, RigidBodyType};
 use rapier3d::geometry::{Collider, ColliderHandle};
 use rapier3d::math::{Rotation, Vector};
-use rapier3d::pipeline::ActiveEvents;
 use serde::{Serialize, Deserialize};
 
 use mlua::{FromLua, HookTriggers, Lua};
 
-use crate::gltf_ext::GltfExt;
+use crate::colliders::{ColliderFactory, Colliders};
+use crate::library::PluginLibrary;
+use crate::plugin::{check_asset, Plugin};
 use crate::user::*;
 use crate::physics::*;
 use crate::events::*;
 use crate::pawn::*;
-use crate::math::{Quat, Vec3};
+use crate::zone::*;
+use crate::joint::*;
+use crate::math::{Quat, Vec2, Vec3};
 use crate::PHYSICS_RATE;
 
+const BROADCAST_TICKS: u64 = 3; // Moving pawns are sent every few physics ticks
+
 static LUA_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/src/lua");
+const LUA_MEMORY_LIMIT: usize = 1 << 18;
+const LUA_GC_STEP_KBYTES: i32 = 64;
+
+// Limits on the assets a game can register
+pub const MAX_ASSETS: usize = 256;
+pub const MAX_ASSET_SIZE: usize = 1024 * 1024 * 2;
+pub const MAX_ASSETS_SIZE: usize = 1024 * 1024 * 40;
+
+// Asset contents by hash, shared between every lobby so ones running the same plugin keep a single copy.
+// Lobbies hold the references, an entry is removed when the last asset using it drops its contents.
+static ASSET_STORE: LazyLock<Mutex<HashMap<u64, Weak<AssetData>>>> = LazyLock::new(Default::default);
+
+/// Asset contents along with what's built from them, which is freed with them
+pub struct AssetData {
+    bytes: Vec<u8>,
+    hash: u64,
+    pub colliders: Mutex<HashMap<ColliderFallback, Colliders>>,
+}
+impl Drop for AssetData {
+    fn drop(&mut self) {
+        let Ok(mut store) = ASSET_STORE.lock() else { return; };
+        // A colliding hash may have taken the entry over, leave it if so
+        if store.get(&self.hash).is_some_and(|shared| shared.strong_count() == 0) {
+            store.remove(&self.hash);
+        }
+    }
+}
+impl Deref for AssetData {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
 
 pub struct Asset {
     pub mime_type: String,
-    pub data: Vec<u8>,
+    pub data: Arc<AssetData>,
+    pub hash: u64,
+}
+impl Asset {
+    pub fn new(mime_type: String, data: Vec<u8>) -> Asset {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mut store = ASSET_STORE.lock().unwrap();
+        // Dropped after the store is unlocked, as dropping the last reference locks it
+        let existing = store.get(&hash).and_then(|shared| shared.upgrade());
+        let data = match &existing {
+            Some(shared) if shared.bytes == data => shared.clone(), // Colliding hashes with different contents aren't shared
+            _ => {
+                let data = Arc::new(AssetData { bytes: data, hash, colliders: Default::default() });
+                store.insert(hash, Arc::downgrade(&data));
+                data
+            }
+        };
+        drop(store);
+        Asset { mime_type, data, hash }
+    }
+    /// Version clients add to the asset's URL, so it can be cached until the contents change
+    pub fn version(&self) -> String {
+        format!("{:016x}", self.hash)
+    }
+    /// Number of distinct asset contents held across every lobby, and their total size
+    pub fn stored() -> (usize, usize) {
+        let store = ASSET_STORE.lock().unwrap();
+        let shared: Vec<Arc<AssetData>> = store.values().filter_map(|shared| shared.upgrade()).collect();
+        // Dropping the last reference locks the store, see `Asset::new`
+        drop(store);
+        shared.iter().fold((0, 0), |(count, size), data| (count + 1, size + data.len()))
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -45,6 +121,8 @@ pub struct GameInfo {
     pub author: String,
 
     pub rotation_increment: Option<f64>,
+    pub collider_fallback: Option<ColliderFallback>, // For models without collider extras
+    pub environment: Option<Environment>,
 }
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -66,27 +144,39 @@ impl Default for LobbySettings {
     }
 }
 
+/// The pawn a ray hit, where, and how far along the ray
+pub type RaycastHit = (PawnId, Vec3, f32);
+
 pub struct Lobby {
     pub name: String,
     pub host: UserId,
     pub info: Option<GameInfo>,
     pub settings: LobbySettings,
     pub start_time: Instant,
+    pub tick: u64, // Physics ticks simulated so far
 
     pub users: HashMap<UserId, User>, // FIXME: Make these both u16
     pub pawns: HashMap<PawnId, Pawn>,   // - Collision probability?
+    pub zones: HashMap<ZoneId, Zone>,
+    pub joints: HashMap<JointId, Joint>,
+    pub groups: HashMap<UserId, PawnGroup>,
     pub assets: HashMap<String, Asset>,
     pub registered_pawns: IndexMap<String, Vec<Pawn>>,
+    pub library: Arc<PluginLibrary>, // Installed plugins the host can start by id
 
     pub world: PhysicsWorld,
+    pub collider_warnings: HashSet<String>, // Models already reported as missing colliders
     pub abort_token: Option<bool>,
+    pub wake: Arc<Notify>, // Resumes stepping once the lobby has gone idle
 
     pub lua: Option<Lua>,
-    pub scheduled_lua_funcs: HashMap<mlua::RegistryKey, u64>,
+    pub scheduled_lua_funcs: IndexMap<mlua::RegistryKey, u64>, // Kept in the order they were scheduled
 
     pub color_allocations: [u32; 7],
     next_user_id: AtomicU64,
     next_pawn_id: AtomicU64,
+    next_zone_id: AtomicU64,
+    next_joint_id: AtomicU64,
 }
 
 impl Lobby {
@@ -97,21 +187,30 @@ impl Lobby {
             info: None,
             settings: Default::default(),
             start_time: Instant::now(),
+            tick: 0,
 
             users: HashMap::new(),
             pawns: HashMap::new(),
+            zones: HashMap::new(),
+            joints: HashMap::new(),
+            groups: HashMap::new(),
             assets: HashMap::new(),
             registered_pawns: IndexMap::new(),
+            library: Default::default(),
 
             world: PhysicsWorld::new(PHYSICS_RATE),
+            collider_warnings: HashSet::new(),
             abort_token: None,
+            wake: Arc::new(Notify::new()),
 
             lua: None,
-            scheduled_lua_funcs: HashMap::new(),
+            scheduled_lua_funcs: IndexMap::new(),
 
             color_allocations: [0; 7],
             next_user_id: AtomicU64::new(1),
             next_pawn_id: AtomicU64::new(1),
+            next_zone_id: AtomicU64::new(1),
+            next_joint_id: AtomicU64::new(1),
         };
         lobby.reset_lua();
         lobby
@@ -123,6 +222,22 @@ impl Lobby {
     pub fn next_pawn_id(&self) -> PawnId {
         PawnId(self.next_pawn_id.fetch_add(1, Ordering::Relaxed))
     }
+    pub fn next_zone_id(&self) -> ZoneId {
+        ZoneId(self.next_zone_id.fetch_add(1, Ordering::Relaxed))
+    }
+    pub fn next_joint_id(&self) -> JointId {
+        JointId(self.next_joint_id.fetch_add(1, Ordering::Relaxed))
+    }
+    pub fn add_user(&mut self, tx: mpsc::UnboundedSender<Message>) -> UserId {
+        let user_id = self.next_user_id();
+        let (color, color_idx) = self.next_color();
+        self.users.insert(user_id, User::new(user_id, tx, color, color_idx));
+        user_id
+    }
+    /// The host, if `token` is theirs
+    pub fn host_with_token(&self, token: &str) -> Option<UserId> {
+        self.users.get(&self.host).filter(|u| u.token == token).map(|u| u.id)
+    }
     pub fn next_color(&mut self) -> (Color, usize) {
         let color_idx = self.color_allocations
             .iter()
@@ -143,12 +258,21 @@ impl Lobby {
         }, color_idx)
     }
 
-    pub fn step(&mut self, send_update_pawns: bool) -> Result<(), Box<dyn Error>> {
-        // Simulate physics
+    // Every tick runs the same phases in order: simulate, sync, timers, callbacks, broadcast
+    pub fn step(&mut self) -> Result<(), Box<dyn Error>> {
+        self.tick += 1;
         self.world.step();
-
-        // Transfer pawn information from rigidbodies
-        let mut dirty_pawns: Vec<&Pawn> = vec![];
+        let moving = self.sync_pawns()?;
+        self.tick_timers()?;
+        self.run_step_callbacks()?;
+        if self.tick % BROADCAST_TICKS == 0 {
+            self.broadcast_moving(moving)?;
+        }
+        Ok(())
+    }
+    // Transfer pawn transforms from their rigidbodies, returning pawns that are still moving
+    fn sync_pawns(&mut self) -> Result<Vec<PawnId>, Box<dyn Error>> {
+        let mut moving: Vec<PawnId> = vec![];
         for pawn in self.pawns.values_mut() {
             if pawn.selected_user.is_some() { continue; } // Ignore selected pawns
 
@@ -157,28 +281,30 @@ impl Lobby {
             pawn.position = Vec3::from(rb.translation());
             pawn.rotation = Quat::from(rb.rotation());
             if !rb.is_sleeping() && rb.is_moving() {
-                dirty_pawns.push(pawn);
+                moving.push(pawn.id);
             }
         }
-        if !dirty_pawns.is_empty() && send_update_pawns {
-            // Send update
-            return self.users.values().send_event(&Event::UpdatePawns {
-                updates: dirty_pawns.iter().map(|p| p.serialize_transform()).collect(),
-                collisions: None,
-            });
-        }
-
-        // Lua callback
-        self.scheduled_lua_funcs.iter_mut().for_each(|(_k, v)| {*v -= 1;}); // Tick down timers
-        let (not_ready, ready) = std::mem::take(&mut self.scheduled_lua_funcs).into_iter().partition(|(_, v)| *v != 0);
-        self.scheduled_lua_funcs = not_ready;
-        for ready_func in ready.into_keys() { // Call completed timers
+        Ok(moving)
+    }
+    // Count down timers, calling those that are due in the order they were scheduled
+    fn tick_timers(&mut self) -> Result<(), Box<dyn Error>> {
+        self.scheduled_lua_funcs.values_mut().for_each(|v| *v = v.saturating_sub(1));
+        let (not_ready, ready): (IndexMap<_, _>, IndexMap<_, _>) = std::mem::take(&mut self.scheduled_lua_funcs)
+            .into_iter().partition(|(_, v)| *v != 0);
+        self.scheduled_lua_funcs = not_ready; // Timers scheduled by callbacks below start counting next tick
+        for ready_func in ready.into_keys() {
             if let Err(e) = self.lua_scope(|lua, _scope, _| {
                 lua.registry_value::<mlua::Function>(&ready_func)?.call::<(), ()>(())
             }) {
                 self.system_chat(Cow::Owned(format!("Lua error in scheduled function: `{}`", e)))?;
             }
         }
+        Ok(())
+    }
+    fn run_step_callbacks(&mut self) -> Result<(), Box<dyn Error>> {
+        // Fire zone callbacks for pawns that entered or left
+        self.update_zones()?;
+
         if let Err(e) = self.lua_scope(|lua, _scope, _| { // Call physics callback
             if let Some(res) = Self::run_lua_callback(lua, "physics", ()) {
                 res?;
@@ -187,9 +313,38 @@ impl Lobby {
         }) {
             self.system_chat(Cow::Owned(format!("Lua error in game.physics: `{}`", e)))?;
         }
-
         Ok(())
     }
+    fn broadcast_moving(&mut self, moving: Vec<PawnId>) -> Result<(), Box<dyn Error>> {
+        // Callbacks may have moved or removed pawns, so read their transforms now
+        let updates: Vec<PawnUpdate> = moving.iter()
+            .filter_map(|id| self.pawns.get(id))
+            .filter(|p| p.selected_user.is_none())
+            .map(|p| p.serialize_transform()).collect();
+        if updates.is_empty() { return Ok(()); }
+
+        // Moving pawns may have entered or left hidden zones
+        self.sync_visibility(Some(&moving))?;
+        self.send_pawn_updates(None, updates)
+    }
+    // Nothing is moving and nothing is scheduled, so stepping can wait for an event
+    pub fn is_idle(&mut self) -> bool {
+        // Only idle after a broadcast, so clients see where everything settled
+        if self.tick % BROADCAST_TICKS != 0 {
+            return false;
+        }
+        let islands = &self.world.island_manager;
+        if !islands.active_dynamic_bodies().is_empty() || !islands.active_kinematic_bodies().is_empty()
+            || !self.scheduled_lua_funcs.is_empty() {
+            return false;
+        }
+        // Games with a physics callback expect it every tick, so they never idle
+        // unless they set `game.physics_while_idle = false`
+        !self.lua_scope(|lua, _scope, _| {
+            let game = lua.globals().get::<_, mlua::Table>("game")?;
+            Ok(game.contains_key("physics")? && game.get::<_, Option<bool>>("physics_while_idle")? != Some(false))
+        }).unwrap_or(true)
+    }
 
     // Cursed lifetime workaround, third argument to FnOnce is just to imply lifetime bounds :|
     // this solution was discovered in the #dark-arts channel on the Rust discord
@@ -211,6 +366,11 @@ impl Lobby {
             f(&lua, scope, &&())
         });
 
+        // LuaJIT won't collect when an allocation hits the limit, so help the incremental collector
+        // along once past half of it. A bounded step rather than a full collection on every callback.
+        if lua.used_memory() > LUA_MEMORY_LIMIT / 2 {
+            lua.gc_step_kbytes(LUA_GC_STEP_KBYTES).ok();
+        }
         self.lua = Some(lua);
 
         result
@@ -226,6 +386,14 @@ impl Lobby {
             None
         }
     }
+    /// Source of a module bundled with the server, for `require`
+    pub fn builtin_lua_module(path: &str) -> Option<&'static str> {
+        LUA_DIR.get_file(format!("{path}.lua")).and_then(|file| file.contents_utf8())
+    }
+    pub fn pawn_proxy<'lua>(lua: &'lua Lua, id: PawnId) -> mlua::Result<mlua::Table<'lua>> {
+        let pawn_proxy_table = lua.globals().get::<_, mlua::Table>("PawnProxy")?;
+        pawn_proxy_table.get::<_, mlua::Function>("new")?.call::<_, mlua::Table>((pawn_proxy_table, id.0))
+    }
 }
 impl mlua::UserData for Lobby {
     fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
@@ -257,10 +425,20 @@ impl mlua::UserData for Lobby {
         method!(time: |this, _lua| {
             Ok((Instant::now() - this.start_time).as_secs_f32())
         });
+        method!(tick: |this, _lua| {
+            Ok(this.tick)
+        });
+        // Call `func` after `ticks` physics ticks, or on the next tick if `ticks` is 0
         method!(timeout: |this, lua, func: mlua::Function, ticks: u64| {
             this.scheduled_lua_funcs.insert(lua.create_registry_value(func)?, ticks);
             Ok(())
         });
+        // Call `func` once `seconds` of simulated time have passed, rounded up to whole ticks
+        method!(timeout_seconds: |this, lua, func: mlua::Function, seconds: f64| {
+            let ticks = (seconds / PHYSICS_RATE as f64).ceil().max(0.) as u64;
+            this.scheduled_lua_funcs.insert(lua.create_registry_value(func)?, ticks);
+            Ok(())
+        });
         method!(system_chat: |this, _lua, message: String| {
             this.system_chat(Cow::Owned(message))
         });
@@ -284,18 +462,28 @@ impl mlua::UserData for Lobby {
         });
         method!(update_pawn: |this, lua, params: mlua::Table| {
             let id = PawnId(params.get("id")?);
+            let is_pawn = params.get_metatable().is_some_and(|mt| lua.globals().get::<_, mlua::Table>("Pawn").is_ok_and(|pawn_mt| mt == pawn_mt));
             let update = PawnUpdate {
                 id,
                 name: params.get("name").ok(),
                 mesh: params.get("mesh").ok(),
                 tint: params.get("tint").ok(),
                 moveable: params.get::<_, mlua::Value>("moveable").ok().and_then(|x| x.as_boolean()),
+                physics: params.get("physics").ok(),
+                // A full pawn table (e.g. from a PawnProxy) sets visibility outright, otherwise only when present
+                owner: if is_pawn || params.contains_key("owner")? {
+                    Some(params.get::<_, Option<u64>>("owner")?.map(UserId))
+                } else { None },
+                visible_to: if is_pawn || params.contains_key("visible_to")? {
+                    Some(params.get::<_, Option<Vec<u64>>>("visible_to")?.map(|v| v.into_iter().map(UserId).collect()))
+                } else { None },
 
                 position: params.get("position").ok(),
                 rotation: params.get("rotation").ok(),
                 select_rotation: params.get("select_rotation").ok(),
 
                 selected: None,
+                shuffle: None,
                 data: params.get("data").ok()
             };
             if let Some(pawn) = this.pawns.get_mut(&id) {
@@ -309,6 +497,11 @@ impl mlua::UserData for Lobby {
                         lua.create_registry_value(callback)?
                     ));
                 }
+                if let Ok(callback) = params.get::<_, mlua::Function>("on_snap") {
+                    pawn.on_snap_callback = Some(Arc::new(
+                        lua.create_registry_value(callback)?
+                    ));
+                }
             }
             this.update_pawns(None, Vec::from([update]))?;
             Ok(())
@@ -317,22 +510,120 @@ impl mlua::UserData for Lobby {
             Ok(this.pawns.get(&PawnId(id)).cloned())
         });
         method!(destroy_pawn: |this, _lua, id: u64| {
-            this.remove_pawns(Vec::from([PawnId(id)]))?;
+            this.remove_pawns(None, Vec::from([PawnId(id)]))?;
+            Ok(())
+        });
+        method!(create_zone: |this, lua, params: mlua::Table| {
+            let mut zone = Zone::from_lua(mlua::Value::Table(params), lua)?;
+            zone.id = this.next_zone_id();
+            Ok(this.add_zone(zone)?.0)
+        });
+        method!(destroy_zone: |this, _lua, id: u64| {
+            this.remove_zone(ZoneId(id)).ok_or("Invalid zone id")?;
+            Ok(())
+        });
+        method!(pawns_in_zone: |this, lua, id: u64| {
+            let zone = this.zones.get(&ZoneId(id)).ok_or("Invalid zone id")?;
+            let mut ids: Vec<PawnId> = zone.contents.iter().copied().collect();
+            ids.sort_by_key(|id| id.0);
+            Ok(ids.into_iter().map(|id| Self::pawn_proxy(lua, id)).collect::<mlua::Result<Vec<mlua::Table>>>()?)
+        });
+        method!(attach_pawns: |this, lua, params: mlua::Table| {
+            let joint = Joint::from_lua(mlua::Value::Table(params), lua)?;
+            Ok(this.attach_pawns(None, joint)?.0)
+        });
+        method!(detach_pawns: |this, _lua, id: u64| {
+            this.detach_pawns(None, Vec::from([JointId(id)]))
+        });
+        method!(set_environment: |this, _lua, environment: Environment| {
+            this.world.configure(environment);
             Ok(())
         });
+        method!(raycast: |this, lua, origin: Vec3, direction: Vec3, max_distance: Option<f32>, filter: PawnFilter| {
+            if let Some((id, position, distance)) = this.raycast(origin, direction, max_distance.unwrap_or(f32::MAX), &filter)? {
+                let hit = lua.create_table()?;
+                hit.set("pawn", Self::pawn_proxy(lua, id)?)?;
+                hit.set("position", position)?;
+                hit.set("distance", distance)?;
+                Ok(Some(hit))
+            } else {
+                Ok(None)
+            }
+        });
+        method!(pawns_in_box: |this, lua, position: Vec3, size: Vec3, rotation: Option<Quat>, filter: PawnFilter| {
+            let rotation = rotation.unwrap_or(Quat::identity());
+            let shape = Cuboid::new(Vector::new(size.x as f32/2., size.y as f32/2., size.z as f32/2.));
+            let ids = this.pawns_overlapping(&Isometry::new(Vector::from(&position), Rotation::from(&rotation).scaled_axis()), &shape, &filter);
+            Ok(ids.into_iter().map(|id| Self::pawn_proxy(lua, id)).collect::<mlua::Result<Vec<mlua::Table>>>()?)
+        });
+        method!(pawns_in_sphere: |this, lua, position: Vec3, radius: f32, filter: PawnFilter| {
+            let ids = this.pawns_overlapping(&Isometry::translation(position.x as f32, position.y as f32, position.z as f32), &Ball::new(radius), &filter);
+            Ok(ids.into_iter().map(|id| Self::pawn_proxy(lua, id)).collect::<mlua::Result<Vec<mlua::Table>>>()?)
+        });
+        method!(nearest_pawn: |this, lua, position: Vec3, max_distance: Option<f32>, filter: PawnFilter| {
+            Ok(this.nearest_pawn(position, max_distance.unwrap_or(f32::MAX), &filter)
+                .map(|id| Self::pawn_proxy(lua, id)).transpose()?)
+        });
+        method!(reveal_pawn: |this, _lua, id: u64, user_id: u64| {
+            this.reveal_pawn(PawnId(id), Vec::from([UserId(user_id)]))
+        });
+        method!(host: |this, _lua| {
+            Ok(this.host.0)
+        });
+        method!(users: |this, lua| {
+            let mut users: Vec<&User> = this.users.values().collect();
+            users.sort_by_key(|u| u.id);
+            users.into_iter().map(|user| {
+                let table = lua.create_table()?;
+                table.set("id", user.id.0)?;
+                table.set("color", user.color.clone())?;
+                table.set("host", user.id == this.host)?;
+                table.set("hand_count", user.hand.len())?;
+                Ok(table)
+            }).collect::<mlua::Result<Vec<mlua::Table>>>().map_err(|e| e.into())
+        });
+        method!(store_pawn: |this, _lua, id: u64, user_id: u64| {
+            this.store_pawn(None, PawnId(id), PawnOrUser::User(UserId(user_id)))
+        });
+        method!(take_pawn: |this, lua, user_id: u64, id: u64, position: Option<Vec3>| {
+            this.take_pawn(UserId(user_id), UserId(user_id), PawnId(id), position)?;
+            Ok(Self::pawn_proxy(lua, PawnId(id))?)
+        });
+        method!(hand: |this, _lua, user_id: u64| {
+            Ok(this.users.get(&UserId(user_id)).ok_or("Invalid user id")?.hand.values().cloned().collect::<Vec<Pawn>>())
+        });
+        method!(reorder_hand: |this, _lua, user_id: u64, order: Vec<u64>| {
+            let (user_id, order) = (UserId(user_id), order.into_iter().map(PawnId).collect::<Vec<_>>());
+            this.reorder_hand(user_id, user_id, order.clone())?;
+            this.users[&user_id].send_event(&Event::ReorderHand { from_id: user_id, order })?;
+            Ok(())
+        });
+        method!(reveal_from_hand: |this, _lua, user_id: u64, id: u64, to_id: Option<u64>| {
+            this.reveal_from_hand(UserId(user_id), UserId(user_id), PawnId(id), to_id.map(UserId))
+        });
+        method!(pass_pawn: |this, _lua, user_id: u64, id: u64, to_id: u64| {
+            this.pass_pawn(UserId(user_id), UserId(user_id), PawnId(id), UserId(to_id))
+        });
+        method!(discard_pawn: |this, _lua, user_id: u64, id: u64, into_id: u64| {
+            this.discard_pawn(UserId(user_id), UserId(user_id), PawnId(id), PawnId(into_id))
+        });
     }
 }
 
 impl Lobby {
     // -- LUA EVENTS --
     pub fn reset_lua(&mut self) {
-        self.scheduled_lua_funcs = HashMap::new();
+        self.scheduled_lua_funcs = IndexMap::new();
+        // Zones hold callbacks into the old runtime
+        for id in self.zones.keys().copied().collect::<Vec<ZoneId>>() {
+            self.remove_zone(id);
+        }
 
         let lua = Lua::new_with(
             mlua::StdLib::MATH | mlua::StdLib::TABLE | mlua::StdLib::STRING,
             mlua::LuaOptions::new()
         ).unwrap();
-        lua.set_memory_limit(1 << 18).expect("Failed to set memory limit for lua VM");
+        lua.set_memory_limit(LUA_MEMORY_LIMIT).expect("Failed to set memory limit for lua VM");
 
         // https://github.com/kikito/lua-sandbox/blob/master/sandbox.lua
         const ALLOWED_GLOBALS: [&str; 22] = [
@@ -373,6 +664,103 @@ impl Lobby {
         }).expect("Error while resetting lua runtime");
     }
 
+    // -- USER EVENTS --
+
+    /// Apply an event received from `user_id`'s connection
+    pub fn handle_event(&mut self, user_id: UserId, event: Event<'_>, headers: &HeaderMap) -> Result<(), Box<dyn Error>> {
+        match event {
+            Event::Join { referrer } => self.user_joined(user_id, referrer, headers),
+
+            Event::AddPawn { pawn } => self.add_pawn(pawn.into_owned()),
+            Event::RemovePawns { ids } => self.remove_pawns(Some(user_id), ids),
+            Event::ClearPawns { } => self.clear_pawns(),
+            Event::UpdatePawns { updates, .. } => self.update_pawns(Some(user_id), updates),
+
+            Event::GrabGroup { ids, origin } => self.grab_group(user_id, ids, origin),
+            Event::MoveGroup { position, rotation } => self.move_group(user_id, position, rotation),
+            Event::ReleaseGroup { } => self.release_group(user_id),
+            Event::AttachPawns { joint } => self.attach_pawns(Some(user_id), joint.into_owned()).map(|_| ()),
+            Event::DetachPawns { ids } => self.detach_pawns(Some(user_id), ids),
+            Event::ExtractPawns { from_id, new_id, into_id, count } => self.extract_pawns(user_id, from_id, new_id, into_id, count),
+            Event::StorePawn { from_id, into_id } => self.store_pawn(Some(user_id), from_id, into_id),
+            Event::TakePawn { from_id, target_id, position_hint } => self.take_pawn(user_id, from_id, target_id, position_hint),
+
+            Event::ReorderHand { from_id, order } => self.reorder_hand(user_id, from_id, order),
+            Event::RevealPawn { from_id, target_id, to_id } => self.reveal_from_hand(user_id, from_id, target_id, to_id),
+            Event::PassPawn { from_id, target_id, to_id } => self.pass_pawn(user_id, from_id, target_id, to_id),
+            Event::DiscardPawn { from_id, target_id, into_id } => self.discard_pawn(user_id, from_id, target_id, into_id),
+
+            Event::RegisterGame { info, assets } => self.register_game(user_id, info, assets),
+            Event::RegisterPlugin { id } => self.register_plugin(user_id, id),
+            Event::Settings(s) => self.settings(user_id, s.into_owned()),
+
+            Event::UpdateUserStatuses { updates } => self.update_user(user_id, updates),
+
+            Event::Chat { content, .. } => self.chat(user_id, content),
+            Event::Ping { idx } => self.ping(user_id, idx),
+
+            _ => Err("Received broadcast-only event".into()),
+        }
+    }
+    pub fn user_joined(&mut self, user_id: UserId, referrer: &str, headers: &HeaderMap) -> Result<(), Box<dyn Error>> {
+        // Hide pawns this user can't see before sending them the table
+        let zones = self.hidden_zones();
+        let hidden: Vec<PawnId> = self.pawns.values().filter(|p| !Self::can_see_among(p, user_id, &zones)).map(|p| p.id).collect();
+        for id in hidden {
+            self.pawns.get_mut(&id).unwrap().hidden_from.insert(user_id);
+        }
+
+        // Get user
+        let user = self.users.get(&user_id).ok_or("Invalid user id")?;
+        
+        println!("User <{:?}> joined lobby [{}] with {} users and {} pawns:",
+            user_id, self.name, self.users.len(), self.pawns.len());
+        println!(" - Referrer: {:?}", referrer);
+        println!(" - Lang: {:?}", headers.get(header::ACCEPT_LANGUAGE));
+        println!(" - UA: {:?}", headers.get(header::USER_AGENT));
+        
+        user.send_event(&Event::Start {
+            id: user_id,
+            host: self.host,
+            color: &user.color,
+            token: &user.token,
+            info: &self.info,
+            assets: self.asset_versions(),
+            settings: &self.settings,
+            users: self.users.values().collect(),
+            pawns: self.pawns.values()
+                .filter(|p| !p.hidden_from.contains(&user_id))
+                .map(|p| p.redacted_for(user_id)).collect(),
+            joints: self.joints.values().collect(),
+            registered_pawns: &self.registered_pawns,
+        })?;
+
+        if self.settings.show_card_counts {
+            for (&id, other) in self.users.iter() {
+                let count = other.hand.len() as u64;
+                self.users.values().send_event(&Event::HandCount { id, count })?;
+            }
+        }
+        
+        // Tell all other users that this user has joined
+        self.users.values()
+            .filter(|u| u.id != user_id)
+            .send_event(&Event::Connect {
+                id: user_id,
+                color: &user.color,
+            })?;
+
+        if let Err(e) = self.lua_scope(|lua, _scope, _| { // Call join callback
+            if let Some(res) = Self::run_lua_callback(lua, "join", user_id.0) {
+                res?;
+            }
+            Ok(())
+        }) {
+            self.system_chat(Cow::Owned(format!("Lua error in game.join: `{}`", e)))?;
+        }
+        Ok(())
+    }
+
     // -- CHAT EVENTS --
 
     pub fn chat(&mut self, user_id: UserId, content: Cow<'_, String>) -> Result<(), Box<dyn Error>> {
@@ -405,75 +793,138 @@ impl Lobby {
         if self.pawns.get(&pawn.id).is_some() { return Err("Pawn ID collision".into()); }
         
         // Deserialize collider
+        if matches!(pawn.data, PawnData::SnapPoint { .. } | PawnData::HiddenZone { .. }) { pawn.moveable = false; }
+        let colliders = match self.pawn_colliders(&pawn) {
+            Ok(colliders) => colliders,
+            Err(e) => { // Let the uploader know what's wrong with their model
+                self.system_chat(Cow::Owned(e.to_string()))?;
+                return Err(e);
+            }
+        };
+
+        // Pawn properties take priority over GLTF extras
         // FIXME: Only enable CCD on cards/thin geometry?
-        let rigid_body = if pawn.moveable { RigidBodyBuilder::dynamic() } else { RigidBodyBuilder::fixed() }
+        let body_properties = colliders.iter().fold(pawn.physics.clone(), |acc, (_, properties)| acc.or(properties));
+        let rigid_body = body_properties.rigid_body(if pawn.moveable { RigidBodyBuilder::dynamic() } else { RigidBodyBuilder::fixed() })
             .translation(Vector::from(&pawn.position))
             .rotation(Rotation::from(&pawn.rotation).scaled_axis())
-            .linear_damping(1.0).angular_damping(0.5)
-            .ccd_enabled(/*matches!(pawn.data, PawnData::Deck { .. }) ||*/true) // Enable CCD on everything for now...
+            .user_data(pawn.id.0 as u128)
             .build();
         pawn.rigid_body = Some(self.world.rigid_body_set.insert(rigid_body));
 
-        let colliders: Box<dyn Iterator<Item = Collider>> = match &pawn.data {
-            PawnData::Deck { .. } => {
-                Box::new(std::iter::once((&pawn.data).try_into().unwrap()))
-            },
-            _ => {
-                if let Some(mesh) = pawn.mesh.as_ref() {
-                    let static_path = Path::new("./static/games").canonicalize()?;
-                    let path = static_path.join(Path::new(mesh)).canonicalize();
-
-                    let gltf_data: Option<(Document, Vec<Data>)> = if let Ok(path) = path {
-                        if path.starts_with(static_path) {
-                            Some(gltf::import(path).map(|(d, b, _)| (d,b))?)
-                        } else { None }
-                    } else if let Some(asset) = self.assets.get(&format!("/{}", mesh)) {
-                        Some(gltf::import_slice(asset.data.as_slice()).map(|(d, b, _)| (d,b))?)
-                    } else { None };
-
-                    if let Some((gltf_document, gltf_buffers)) = gltf_data {
-                        Box::new(gltf_document.colliders(gltf_buffers.as_slice()).map(|collider| {
-                            collider
-                                .friction(0.7).mass(0.01)
-                                .active_events(ActiveEvents::COLLISION_EVENTS).build()
-                        }))
-                    } else {
-                        Box::new(std::iter::empty())
-                    }
-                } else {
-                    Box::new(std::iter::empty())
-                }
-            }
-        };
-        for collider in colliders {
-            self.world.insert_with_parent(collider, pawn.rigid_body.ok_or("Pawn missing rigidbody")?);
-        }
+        self.insert_pawn_colliders(&pawn, colliders)?;
 
         // Tell other users that this was added
-        self.users.values().send_event(&Event::AddPawn { pawn: Cow::Borrowed(&pawn) })?;
+        pawn.hidden_from = self.users.keys().filter(|&&id| !self.can_see(&pawn, id)).copied().collect();
+        self.send_add_pawn(&pawn)?;
         
         // Add pawn to lobby
+        let is_zone = matches!(pawn.data, PawnData::HiddenZone { .. });
         self.pawns.insert(pawn.id, pawn);
 
+        if is_zone { self.sync_visibility(None)?; }
+
         Ok(())
     }
-    pub fn remove_pawn(&mut self, id: PawnId) -> Option<Pawn> {
+    fn pawn_colliders(&mut self, pawn: &Pawn) -> Result<Vec<(ColliderBuilder, PhysicsProperties)>, Box<dyn Error>> {
+        let colliders = match (&pawn.data, pawn.mesh.as_ref()) {
+            (PawnData::Deck { .. }, _) => Vec::from([((&pawn.data).try_into().unwrap(), Default::default())]),
+            (_, Some(mesh)) => {
+                let fallback = pawn.physics.collider_fallback
+                    .or(self.info.as_ref().and_then(|i| i.collider_fallback))
+                    .unwrap_or_default();
+                let (colliders, generated) = ColliderFactory::colliders(mesh, &self.assets, fallback)
+                    .map_err(|e| format!("Failed to load colliders for `{}`: {}", mesh, e))?;
+                // Warn once per model so authors add explicit colliders
+                if generated && self.collider_warnings.insert(mesh.clone()) {
+                    self.system_chat(Cow::Owned(match fallback {
+                        ColliderFallback::None => format!("Model `{}` has no collider extras, pawns using it will have no collisions", mesh),
+                        _ => format!("Model `{}` has no collider extras, using a generated collider instead", mesh),
+                    }))?;
+                }
+                colliders
+            },
+            (_, None) => vec![],
+        };
+        // Triangle meshes have no volume, so they can only be used on immovable pawns
+        if pawn.moveable && colliders.iter().any(|(collider, _)| collider.shape.as_trimesh().is_some()) {
+            return Err("Trimesh colliders are only supported on non-moveable pawns".into());
+        }
+        Ok(colliders)
+    }
+    fn insert_pawn_colliders(&mut self, pawn: &Pawn, colliders: Vec<(ColliderBuilder, PhysicsProperties)>) -> Result<(), Box<dyn Error>> {
+        let rb_handle = pawn.rigid_body.ok_or("Pawn missing rigidbody")?;
+        let collider_count = colliders.len();
+        for (collider, properties) in colliders {
+            // Pawn properties take priority over GLTF extras
+            let collider = pawn.physics.or(&properties).collider(collider, collider_count).build();
+            self.world.insert_with_parent(collider, rb_handle);
+        }
+        Ok(())
+    }
+    /// Replace a pawn's colliders and body properties after its mesh or physics changed
+    fn rebuild_pawn_physics(&mut self, pawn: &Pawn) -> Result<(), Box<dyn Error>> {
+        let colliders = self.pawn_colliders(pawn)?;
+
+        let rb_handle = pawn.rigid_body.ok_or("Pawn missing rigidbody")?;
+        let rb = self.world.rigid_body_set.get_mut(rb_handle).ok_or("Rigidbody handle invalid")?;
+        colliders.iter().fold(pawn.physics.clone(), |acc, (_, properties)| acc.or(properties)).apply(rb);
+
+        let collider_handles: Vec<ColliderHandle> = rb.colliders().to_vec();
+        for handle in collider_handles {
+            self.world.remove_collider(handle);
+        }
+        self.insert_pawn_colliders(pawn, colliders)
+    }
+    pub fn remove_pawn(&mut self, id: PawnId) -> Result<Option<Pawn>, Box<dyn Error>> {
+        let Some(pawn) = self.pawns.get(&id) else { return Ok(None) };
         // Remove rigidbody first
-        if let Some(rb_handle) = self.pawns.get(&id)?.rigid_body {
+        if let Some(rb_handle) = pawn.rigid_body {
             self.world.remove_rigidbody(rb_handle);
         }
 
-        let mut pawn = self.pawns.remove(&id)?;
+        for zone in self.zones.values_mut() {
+            zone.contents.remove(&id);
+        }
+        for group in self.groups.values_mut() {
+            group.offsets.retain(|(pawn_id, _)| *pawn_id != id);
+        }
+        // Rapier removes the impulse joints along with the rigidbody
+        let detached: Vec<JointId> = self.joints.values()
+            .filter(|joint| joint.pawns.0 == id || joint.pawns.1 == id)
+            .map(|joint| joint.id).collect();
+        if !detached.is_empty() {
+            for joint_id in &detached {
+                self.joints.remove(joint_id);
+            }
+            self.users.values().send_event(&Event::DetachPawns { ids: detached })?;
+        }
+
+        let mut pawn = self.pawns.remove(&id).unwrap();
         pawn.rigid_body = None;
-        Some(pawn)
+        Ok(Some(pawn))
     }
-    pub fn remove_pawns(&mut self, pawn_ids: Vec<PawnId>) -> Result<(), Box<dyn Error>> {
+    /// Fails if a user (rather than the game) is acting on any of `ids` that are hidden from them
+    fn check_visible(&self, user_id: Option<UserId>, ids: &[PawnId]) -> Result<(), Box<dyn Error>> {
+        let Some(user_id) = user_id else { return Ok(()); };
+        if ids.iter().filter_map(|id| self.pawns.get(id)).any(|pawn| !self.can_see(pawn, user_id)) {
+            return Err("User attempting to use a pawn hidden from them".into());
+        }
+        Ok(())
+    }
+    pub fn remove_pawns(&mut self, user_id: Option<UserId>, pawn_ids: Vec<PawnId>) -> Result<(), Box<dyn Error>> {
+        self.check_visible(user_id, &pawn_ids)?;
+
         // Remove pawn from lobby
+        let mut removed_zone = false;
         for id in &pawn_ids {
-            self.remove_pawn(*id);
+            removed_zone |= self.remove_pawn(*id)?.is_some_and(|p| matches!(p.data, PawnData::HiddenZone { .. }));
         }
         
-        self.users.values().send_event(&Event::RemovePawns { ids: pawn_ids })
+        self.users.values().send_event(&Event::RemovePawns { ids: pawn_ids })?;
+
+        if removed_zone { self.sync_visibility(None)?; }
+        Ok(())
     }
     pub fn clear_pawns(&mut self) -> Result<(), Box<dyn Error>> {
         // Remove pawn rigidbodies from lobby
@@ -484,9 +935,14 @@ impl Lobby {
             self.world.remove_rigidbody(rb_handle);
         }
         self.pawns = HashMap::new();
+        for zone in self.zones.values_mut() {
+            zone.contents = HashSet::new();
+        }
+        self.joints = HashMap::new();
+        self.groups = HashMap::new();
 
         for user in self.users.values_mut() {
-            user.hand = HashMap::new();
+            user.hand = IndexMap::new();
         }
         for &id in self.users.keys() {
             self.users.values().send_event(&Event::HandCount { id, count: 0 })?;
@@ -494,131 +950,216 @@ impl Lobby {
         
         self.users.values().send_event(&Event::ClearPawns {})
     }
-    pub fn update_pawns(&mut self, user_id: Option<UserId>, mut updates: Vec<PawnUpdate>) -> Result<(), Box<dyn Error>> {
+    pub fn update_pawns(&mut self, user_id: Option<UserId>, updates: Vec<PawnUpdate>) -> Result<(), Box<dyn Error>> {
+        let (updates, snapped) = self.apply_pawn_updates(user_id, updates, false)?;
+
+        // Relay to other users that these pawns were changed
+        self.sync_visibility(Some(&updates.iter().map(|u| u.id).collect::<Vec<_>>()))?;
+        self.send_pawn_updates(user_id, updates)?;
+
+        // The user who released a pawn needs to know where it snapped to
+        if let Some(user) = user_id.and_then(|id| self.users.get(&id)).filter(|_| !snapped.is_empty()) {
+            user.send_event(&Event::UpdatePawns { updates: snapped, collisions: None, tick: Some(self.tick) })?;
+        }
+        Ok(())
+    }
+    /// Apply updates without relaying them, returning the sanitized updates and the transforms of any snapped pawns.
+    /// Grouped updates leave grab callbacks and snapping to the group.
+    fn apply_pawn_updates(&mut self, user_id: Option<UserId>, mut updates: Vec<PawnUpdate>, grouped: bool)
+        -> Result<(Vec<PawnUpdate>, Vec<PawnUpdate>), Box<dyn Error>> {
         // Iterate through and update pawns, sanitize updates when relaying:
         //  - Discard updates updating invalid pawns, non-owned pawns
         //  - Discard position and rotation changes on updates to immovable pawns
-        updates = updates.into_iter().map(|mut update| {
+        let mut snapped: Vec<PawnUpdate> = vec![];
+        updates = updates.into_iter().map(|update| {
             let pawn_id = update.id;
+            // Pawns hidden from a user can't be touched by them, even by guessing their id
+            if let Some(user_id) = user_id {
+                if !self.can_see(self.pawns.get(&pawn_id).ok_or("Trying to update invalid pawn")?, user_id) {
+                    println!("User <{user_id:?}> trying to update hidden pawn");
+                    return Ok(None);
+                }
+            }
             let mut pawn: Pawn = self.pawns.remove(&pawn_id).ok_or("Trying to update invalid pawn")?;
 
-            // If a user is updating this pawn
-            if let Some(user_id) = user_id {
-                if let Some(selected_user) = pawn.selected_user { // If a user has already selected this pawn
-                    if selected_user != user_id {
-                        // and if the selected users don't match
-                        println!("User <{user_id:?}> trying to update non-owned pawn");
-                        update = PawnUpdate {
-                            id: update.id,
-                            ..Default::default()
-                        };
-                    }
-                }/* else { // If a user hasn't selected this pawn
-                    if !update.selected.is_some_and(|x| x) {
-                        // and we try to update it without setting selected to true
-                        println!("User <{user_id:?}> trying to update non-owned pawn");
-                        update = PawnUpdate {
-                            id: update.id,
-                            ..Default::default()
-                        };
-                    }
-                }*/
-            }
-
-            if !pawn.moveable {
-                update.position = None;
-                update.rotation = None;
-                update.select_rotation = None;
-            }
-            
-            // Update struct values
-            let update = pawn.patch(update, user_id);
-            if let Some(selected) = update.selected {
-                if selected {
-                    if let Err(e) = self.lua_scope(|lua, _scope, _| {
-                        if let Some(callback) = pawn.on_grab_callback.as_ref() {
-                            lua.registry_value::<mlua::Function>(callback)?.call::<_, ()>(user_id.unwrap_or_default().0)
-                        } else { Ok(()) }
-                    }) {
-                        self.system_chat(Cow::Owned(format!("Lua error in on_grab: `{}`", e)))?;
-                    }
-                } else {
-                    if let Err(e) = self.lua_scope(|lua, _scope, _| {
-                        if let Some(callback) = pawn.on_release_callback.as_ref() {
-                            lua.registry_value::<mlua::Function>(callback)?.call::<_, ()>(user_id.unwrap_or_default().0)
-                        } else { Ok(()) }
-                    }) {
-                        self.system_chat(Cow::Owned(format!("Lua error in on_release: `{}`", e)))?;
-                    }
+            // Put the pawn back even if the update failed partway, so it never loses its rigidbody
+            let update = self.apply_pawn_update(user_id, &mut pawn, update, grouped, &mut snapped);
+            self.pawns.insert(pawn_id, pawn);
+            update.map(Some)
+        }).collect::<Result<Vec<_>, Box<dyn Error>>>()?.into_iter().flatten().collect();
+
+        Ok((updates, snapped))
+    }
+    /// Apply one update to a pawn that's been taken out of `self.pawns`
+    fn apply_pawn_update(&mut self, user_id: Option<UserId>, pawn: &mut Pawn, mut update: PawnUpdate, grouped: bool,
+                         snapped: &mut Vec<PawnUpdate>) -> Result<PawnUpdate, Box<dyn Error>> {
+        // If a user is updating this pawn
+        if let Some(user_id) = user_id {
+            if let Some(selected_user) = pawn.selected_user { // If a user has already selected this pawn
+                if selected_user != user_id {
+                    // and if the selected users don't match
+                    println!("User <{user_id:?}> trying to update non-owned pawn");
+                    update = PawnUpdate {
+                        id: update.id,
+                        ..Default::default()
+                    };
+                }
+            }/* else { // If a user hasn't selected this pawn
+                if !update.selected.is_some_and(|x| x) {
+                    // and we try to update it without setting selected to true
+                    println!("User <{user_id:?}> trying to update non-owned pawn");
+                    update = PawnUpdate {
+                        id: update.id,
+                        ..Default::default()
+                    };
+                }
+            }*/
+        }
+
+        // Only the server can change visibility or physical properties
+        if user_id.is_some() {
+            update.owner = None;
+            update.visible_to = None;
+            update.physics = None;
+        }
+
+        if !pawn.moveable {
+            update.position = None;
+            update.rotation = None;
+            update.select_rotation = None;
+        }
+
+        // Users can only see part of a concealable deck, so never trust the contents they send back.
+        // The only way a client changes contents through an update is shuffling, which they ask for explicitly.
+        if user_id.is_some() && pawn.concealable() {
+            if let (Some(PawnData::Deck { contents: update_contents, .. }), PawnData::Deck { contents, .. })
+                = (update.data.as_mut(), &pawn.data) {
+                let mut contents = contents.clone();
+                if update.shuffle == Some(true) {
+                    contents.shuffle(&mut rand::thread_rng());
+                    pawn.revealed_to.clear();
                 }
+                *update_contents = contents;
             }
-            
-            // Update physics
-            if let Some(PawnData::Deck { .. }) = &update.data {
-                let collider_handles: Vec<ColliderHandle> = {
-                    let rb_handle = pawn.rigid_body.ok_or("Pawn missing rigidbody")?;
-                    let rb = self.world.rigid_body_set.get_mut(rb_handle).ok_or("Rigidbody handle invalid")?;
-                    rb.colliders().iter().map(|h| *h).collect()
-                };
-                for handle in collider_handles {
-                    self.world.remove_collider(handle);
+        }
+        
+        // Update struct values
+        let mut update = pawn.patch(update, user_id);
+        if let Some(selected) = update.selected.filter(|_| !grouped) {
+            if selected {
+                if let Err(e) = self.lua_scope(|lua, _scope, _| {
+                    if let Some(callback) = pawn.on_grab_callback.as_ref() {
+                        lua.registry_value::<mlua::Function>(callback)?.call::<_, ()>(user_id.unwrap_or_default().0)
+                    } else { Ok(()) }
+                }) {
+                    self.system_chat(Cow::Owned(format!("Lua error in on_grab: `{}`", e)))?;
                 }
+            } else {
+                if let Err(e) = self.lua_scope(|lua, _scope, _| {
+                    if let Some(callback) = pawn.on_release_callback.as_ref() {
+                        lua.registry_value::<mlua::Function>(callback)?.call::<_, ()>(user_id.unwrap_or_default().0)
+                    } else { Ok(()) }
+                }) {
+                    self.system_chat(Cow::Owned(format!("Lua error in on_release: `{}`", e)))?;
+                }
+            }
+        }
 
-                self.world.insert_with_parent((update.data.as_ref().unwrap()).try_into().unwrap(),
-                                            pawn.rigid_body.ok_or("Pawn missing rigidbody")?);
+        // Snap released pawns to the nearest snap point that accepts them
+        let mut snapped_to = None;
+        if update.selected == Some(false) && pawn.moveable && !grouped {
+            if let Some((snap_point, cell, position)) = self.nearest_snap(&pawn) {
+                let increment = self.info.as_ref().and_then(|i| i.rotation_increment).unwrap_or(FRAC_PI_2) as f32;
+                pawn.position = position;
+                pawn.rotation = snap_point.snap_rotation(&pawn.rotation, increment);
+                pawn.select_rotation = snap_point.snap_rotation(&pawn.select_rotation, increment);
+                update.position = Some(pawn.position);
+                update.rotation = Some(pawn.rotation);
+                update.select_rotation = Some(pawn.select_rotation);
+                snapped.push(pawn.serialize_transform());
+
+                snapped_to = Some((snap_point.on_snap_callback.clone(), cell));
+            }
+        }
+        if let Some((Some(callback), (x, y))) = snapped_to.as_ref() {
+            if let Err(e) = self.lua_scope(|lua, _scope, _| {
+                let cell = Vec2 { x: *x as f64, y: *y as f64 };
+                lua.registry_value::<mlua::Function>(callback)?.call::<_, ()>((pawn.id.0, cell, user_id.unwrap_or_default().0))
+            }) {
+                self.system_chat(Cow::Owned(format!("Lua error in on_snap: `{}`", e)))?;
             }
-            if pawn.moveable {
+        }
+        
+        // Update physics
+        if let Some(PawnData::Deck { .. }) = &update.data {
+            let collider_handles: Vec<ColliderHandle> = {
                 let rb_handle = pawn.rigid_body.ok_or("Pawn missing rigidbody")?;
                 let rb = self.world.rigid_body_set.get_mut(rb_handle).ok_or("Rigidbody handle invalid")?;
-                // Update mesh colliders
-                if update.mesh.is_some() {
-                    todo!("Update mesh colliders");
-                }
-                // Don't simulate selected pawns
-                rb.set_body_type(if pawn.selected_user.is_none() {
-                    RigidBodyType::Dynamic
+                rb.colliders().iter().map(|h| *h).collect()
+            };
+            for handle in collider_handles {
+                self.world.remove_collider(handle);
+            }
+
+            self.world.insert_with_parent(pawn.physics.collider((update.data.as_ref().unwrap()).try_into().unwrap(), 1).build(),
+                                        pawn.rigid_body.ok_or("Pawn missing rigidbody")?);
+        }
+        // Update mesh colliders and physical properties
+        if update.physics.is_some() || (update.mesh.is_some() && !matches!(pawn.data, PawnData::Deck { .. })) {
+            // Keep the old colliders rather than dropping the pawn
+            if let Err(e) = self.rebuild_pawn_physics(&pawn) {
+                self.system_chat(Cow::Owned(e.to_string()))?;
+            }
+        }
+        if pawn.moveable {
+            let rb_handle = pawn.rigid_body.ok_or("Pawn missing rigidbody")?;
+            let rb = self.world.rigid_body_set.get_mut(rb_handle).ok_or("Rigidbody handle invalid")?;
+            // Don't simulate selected pawns
+            rb.set_body_type(if pawn.selected_user.is_none() {
+                RigidBodyType::Dynamic
+            } else {
+                RigidBodyType::KinematicPositionBased
+            }, true);
+            for collider_handle in rb.colliders().iter() {
+                let collider = self.world.collider_set.get_mut(*collider_handle).ok_or("Invalid collider handle")?;
+                collider.set_sensor(pawn.selected_user.is_some());
+            }
+            // Update position and velocity
+            if update.position.is_some() || update.rotation.is_some() {
+                let old_position: &Vector<f32> = rb.translation();
+                let position: Vector<f32> = Vector::from(&pawn.position);
+
+                let rotation: Rotation<f32> = Rotation::from(&pawn.rotation);
+                let time_difference = (Instant::now() - pawn.last_updated).as_secs_f32();
+                let velocity: Vector<f32> = if user_id.is_some() && snapped_to.is_none() {
+                    (position - old_position)/time_difference.max(1.0/20.0)
                 } else {
-                    RigidBodyType::KinematicPositionBased
-                }, true);
-                for collider_handle in rb.colliders().iter() {
-                    let collider = self.world.collider_set.get_mut(*collider_handle).ok_or("Invalid collider handle")?;
-                    collider.set_sensor(pawn.selected_user.is_some());
-                }
-                // Update position and velocity
-                if update.position.is_some() || update.rotation.is_some() {
-                    let old_position: &Vector<f32> = rb.translation();
-                    let position: Vector<f32> = Vector::from(&pawn.position);
-
-                    let rotation: Rotation<f32> = Rotation::from(&pawn.rotation);
-                    let time_difference = (Instant::now() - pawn.last_updated).as_secs_f32();
-                    let velocity: Vector<f32> = if user_id.is_some() {
-                        (position - old_position)/time_difference.max(1.0/20.0)
-                    } else {
-                        vector![0.0, 0.0, 0.0]
-                    };
+                    vector![0.0, 0.0, 0.0]
+                };
 
-                    let wake = true;
-                    rb.set_translation(position, wake);
-                    rb.set_rotation(rotation, wake);
-                    rb.set_linvel(velocity, wake);
-                    rb.set_angvel(vector![0.0, 0.0, 0.0], wake);
-                }
+                let wake = true;
+                rb.set_translation(position, wake);
+                rb.set_rotation(rotation, wake);
+                rb.set_linvel(velocity, wake);
+                rb.set_angvel(vector![0.0, 0.0, 0.0], wake);
             }
+        }
 
-            // Refresh last updated
-            pawn.last_updated = Instant::now();
-            self.pawns.insert(pawn_id, pawn);
+        // Refresh last updated
+        pawn.last_updated = Instant::now();
 
-            Ok(update)
-        }).collect::<Result<Vec<_>, Box<dyn Error>>>()?;
-        
-        // Relay to other users that these pawns were changed
-        self.users.values()
-            .filter(|u| !user_id.is_some_and(|user_id| u.id == user_id))
-            .send_event(&Event::UpdatePawns { updates, collisions: None })
+        Ok(update)
+    }
+    /// The closest snap point that accepts `pawn`, with the cell and position it would snap to
+    fn nearest_snap(&self, pawn: &Pawn) -> Option<(&Pawn, (u64, u64), Vec3)> {
+        self.pawns.values()
+            .filter_map(|snap_point| snap_point.snap(pawn).map(|(cell, position, distance)| (snap_point, cell, position, distance)))
+            .min_by(|a, b| a.3.total_cmp(&b.3))
+            .map(|(snap_point, cell, position, _)| (snap_point, cell, position))
     }
-    pub fn extract_pawns(&mut self, _user_id: UserId, from_id: PawnId, new_id: PawnId, into_id: Option<UserId>, count: Option<u64>) -> Result<(), Box<dyn Error>> {
+    pub fn extract_pawns(&mut self, user_id: UserId, from_id: PawnId, new_id: PawnId, into_id: Option<UserId>, count: Option<u64>) -> Result<(), Box<dyn Error>> {
         if self.pawns.contains_key(&new_id) { return Err("Attempting to extract with existing ID".into()); }
+        self.check_visible(Some(user_id), &[from_id])?;
 
         let from = self.pawns.get_mut(&from_id).ok_or("Trying to extract from missing pawn")?;
 
@@ -661,7 +1202,7 @@ impl Lobby {
                             self.world.remove_collider(handle);
                         }
         
-                        self.world.insert_with_parent((&from.data).try_into().unwrap(),
+                        self.world.insert_with_parent(from.physics.collider((&from.data).try_into().unwrap(), 1).build(),
                                                     from.rigid_body.ok_or("Pawn missing rigidbody")?);
                     }
 
@@ -679,24 +1220,26 @@ impl Lobby {
             _ => Err("Trying to extract from non-container pawn".into()),
         }?;
 
-        self.users.values().send_event(&Event::UpdatePawns {
-            updates: vec![PawnUpdate {
-                id: from.id,
-                data: Some(from.data.clone()),
-                ..Default::default()
-            }],
-            collisions: None
-        })?;
+        let update = PawnUpdate {
+            id: from.id,
+            data: Some(from.data.clone()),
+            ..Default::default()
+        };
+        self.send_pawn_updates(None, vec![update])?;
 
         match into_id {
             Some(into_id) => {
                 self.pawns.insert(new_id, to);
-                self.store_pawn(new_id, PawnOrUser::User(into_id))
+                self.store_pawn(None, new_id, PawnOrUser::User(into_id))
             },
             None => self.add_pawn(to),
         }
     }
-    pub fn store_pawn(&mut self, from_id: PawnId, into_id: PawnOrUser) -> Result<(), Box<dyn Error>> {
+    pub fn store_pawn(&mut self, user_id: Option<UserId>, from_id: PawnId, into_id: PawnOrUser) -> Result<(), Box<dyn Error>> {
+        match into_id {
+            PawnOrUser::Pawn(into_id) => self.check_visible(user_id, &[from_id, into_id])?,
+            PawnOrUser::User(_) => self.check_visible(user_id, &[from_id])?,
+        }
         if !match into_id {
             PawnOrUser::User(id) => self.pawns.contains_key(&from_id) && self.users.contains_key(&id),
             PawnOrUser::Pawn(id) => self.pawns.contains_key(&from_id) && self.pawns.contains_key(&id),
@@ -705,10 +1248,33 @@ impl Lobby {
             return Err("From/into pawn missing when merging".into());
         }
 
-        let from = self.remove_pawn(from_id).unwrap();
+        if let PawnOrUser::Pawn(into_id) = into_id {
+            self.check_merge(self.pawns.get(&from_id).unwrap(), into_id)?;
+        }
+
+        let from = self.remove_pawn(from_id)?.unwrap();
+        self.insert_pawn_into(from, into_id)?;
+
+        self.users.values().send_event(&Event::RemovePawns { ids: vec![from_id] })
+    }
+    /// Check that `from` can be merged into `into_id` before taking it off the table or out of a hand,
+    /// so a rejected merge doesn't lose the pawn
+    fn check_merge(&self, from: &Pawn, into_id: PawnId) -> Result<(), Box<dyn Error>> {
+        let into = self.pawns.get(&into_id).ok_or("Trying to merge into missing pawn")?;
+        match (&into.data, &from.data) {
+            (PawnData::Container { .. }, _) => Ok(()),
+            (PawnData::Deck { size, .. }, PawnData::Deck { size: from_size, .. }) if size == from_size => Ok(()),
+            (PawnData::Deck { .. }, PawnData::Deck { .. }) => Err("Trying to merge decks of different sizes".into()),
+            (PawnData::Deck { .. }, _) => Err("Trying to merge non-deck pawn into deck".into()),
+            _ => Err("Trying to merge into non-container pawn".into()),
+        }
+    }
+    /// Merge a pawn that isn't on the table into a container, deck, or user's hand
+    fn insert_pawn_into(&mut self, from: Pawn, into_id: PawnOrUser) -> Result<(), Box<dyn Error>> {
+        let from_id = from.id;
         match into_id {
             PawnOrUser::Pawn(into_id) => {
-                let into = self.pawns.get_mut(&into_id).unwrap();
+                let into = self.pawns.get_mut(&into_id).ok_or("Trying to merge into missing pawn")?;
 
                 let flipped = into.flipped();
                 match &mut into.data {
@@ -720,6 +1286,7 @@ impl Lobby {
                     },
                     PawnData::Deck { contents: into_contents, .. } => {
                         if let PawnData::Deck { contents: mut from_contents, ..} = from.data {
+                            into.revealed_to.clear();
                             if flipped {
                                 into_contents.append(&mut from_contents);
                             } else {
@@ -738,56 +1305,41 @@ impl Lobby {
                                     self.world.remove_collider(handle);
                                 }
                 
-                                self.world.insert_with_parent((&into.data).try_into().unwrap(),
+                                self.world.insert_with_parent(into.physics.collider((&into.data).try_into().unwrap(), 1).build(),
                                                             into.rigid_body.ok_or("Pawn missing rigidbody")?);
                             }
+                        } else {
+                            return Err("Trying to merge non-deck pawn into deck".into());
                         }
                         Ok(())
                     },
                     _ => Err("Trying to merge into non-container pawn".into()),
                 }?;
 
-                self.users.values().send_event(&Event::UpdatePawns {
-                    updates: vec![PawnUpdate {
-                        id: into.id,
-                        data: Some(into.data.clone()),
-                        ..Default::default()
-                    }],
-                    collisions: None
-                })?;
+                let update = PawnUpdate {
+                    id: into.id,
+                    data: Some(into.data.clone()),
+                    ..Default::default()
+                };
+                self.send_pawn_updates(None, vec![update])?;
             },
             PawnOrUser::User(into_id) => {
-                let into = self.users.get_mut(&into_id).unwrap();
+                let into = self.users.get_mut(&into_id).ok_or("Trying to merge into missing user")?;
                 into.hand.insert(from_id, from);
 
                 into.send_event(&Event::AddPawnToHand {
                     pawn: Cow::Borrowed(into.hand.get(&from_id).unwrap())
                 })?;
 
-                if self.settings.show_card_counts {
-                    let count = into.hand.len() as u64;
-                    self.users.values().send_event(&Event::HandCount {
-                        id: into_id, count
-                    })?;
-                }
+                self.send_hand_count(into_id)?;
             }
         }
-        self.users.values().send_event(&Event::RemovePawns { ids: vec![from_id] })
+        Ok(())
     }
     pub fn take_pawn(&mut self, user_id: UserId, from_id: UserId, target_id: PawnId, position_hint: Option<Vec3>) -> Result<(), Box<dyn Error>> {
         if user_id != from_id { return Err("Attempting to take pawn from non-self user".into()); }
 
-        let mut taken_pawn = self.users
-                            .get_mut(&from_id)
-                            .ok_or("Lobby missing user")?.hand
-                            .remove(&target_id).ok_or("User doesn't have requested pawn")?;
-
-        if self.settings.show_card_counts {
-            let count = self.users.get_mut(&from_id).unwrap().hand.len() as u64;
-            self.users.values().send_event(&Event::HandCount {
-                id: from_id, count
-            })?;
-        }
+        let mut taken_pawn = self.remove_from_hand(from_id, target_id)?;
         
         if let Some(position_hint) = position_hint {
             taken_pawn.position = position_hint;
@@ -796,6 +1348,456 @@ impl Lobby {
         self.add_pawn(taken_pawn)
     }
 
+    // -- HAND EVENTS --
+
+    /// Remove a pawn from a user's hand, keeping their client and everyone's hand counts in sync
+    pub fn remove_from_hand(&mut self, from_id: UserId, target_id: PawnId) -> Result<Pawn, Box<dyn Error>> {
+        let from = self.users.get_mut(&from_id).ok_or("Lobby missing user")?;
+        let pawn = from.hand.shift_remove(&target_id).ok_or("User doesn't have requested pawn")?;
+
+        from.send_event(&Event::RemovePawnsFromHand { ids: vec![target_id] })?;
+        self.send_hand_count(from_id)?;
+        Ok(pawn)
+    }
+    pub fn send_hand_count(&self, id: UserId) -> Result<(), Box<dyn Error>> {
+        if self.settings.show_card_counts {
+            let count = self.users.get(&id).ok_or("Lobby missing user")?.hand.len() as u64;
+            self.users.values().send_event(&Event::HandCount { id, count })?;
+        }
+        Ok(())
+    }
+    pub fn reorder_hand(&mut self, user_id: UserId, from_id: UserId, order: Vec<PawnId>) -> Result<(), Box<dyn Error>> {
+        if user_id != from_id { return Err("Attempting to reorder hand of non-self user".into()); }
+
+        let user = self.users.get_mut(&from_id).ok_or("Lobby missing user")?;
+        if order.len() != user.hand.len() || !order.iter().all(|id| user.hand.contains_key(id)) {
+            return Err("Hand order doesn't match hand".into());
+        }
+        user.hand = order.iter().map(|id| user.hand.swap_remove_entry(id).unwrap()).collect();
+
+        Ok(())
+    }
+    pub fn reveal_from_hand(&self, user_id: UserId, from_id: UserId, target_id: PawnId, to_id: Option<UserId>) -> Result<(), Box<dyn Error>> {
+        if user_id != from_id { return Err("Attempting to reveal pawn from non-self user".into()); }
+
+        let pawn = self.users
+                       .get(&from_id).ok_or("Lobby missing user")?.hand
+                       .get(&target_id).ok_or("User doesn't have requested pawn")?;
+        let event = Event::ShowPawn { from_id, pawn: Cow::Borrowed(pawn) };
+        match to_id {
+            Some(to_id) => self.users.get(&to_id).ok_or("Revealing to missing user")?.send_event(&event)?,
+            None => self.users.values().filter(|u| u.id != from_id).send_event(&event)?,
+        }
+        Ok(())
+    }
+    pub fn pass_pawn(&mut self, user_id: UserId, from_id: UserId, target_id: PawnId, to_id: UserId) -> Result<(), Box<dyn Error>> {
+        if user_id != from_id { return Err("Attempting to pass pawn from non-self user".into()); }
+        if !self.users.contains_key(&to_id) { return Err("Passing pawn to missing user".into()); }
+
+        let pawn = self.remove_from_hand(from_id, target_id)?;
+        self.insert_pawn_into(pawn, PawnOrUser::User(to_id))
+    }
+    pub fn discard_pawn(&mut self, user_id: UserId, from_id: UserId, target_id: PawnId, into_id: PawnId) -> Result<(), Box<dyn Error>> {
+        if user_id != from_id { return Err("Attempting to discard pawn from non-self user".into()); }
+        if !matches!(self.pawns.get(&into_id).map(|p| &p.data), Some(PawnData::Deck { .. })) {
+            return Err("Discarding into missing or non-deck pawn".into());
+        }
+        let hand = &self.users.get(&from_id).ok_or("Lobby missing user")?.hand;
+        self.check_merge(hand.get(&target_id).ok_or("User doesn't have requested pawn")?, into_id)?;
+
+        let pawn = self.remove_from_hand(from_id, target_id)?;
+        self.insert_pawn_into(pawn, PawnOrUser::Pawn(into_id))
+    }
+
+    // -- GROUP EVENTS --
+
+    pub fn grab_group(&mut self, user_id: UserId, mut ids: Vec<PawnId>, origin: Option<Vec3>) -> Result<(), Box<dyn Error>> {
+        // A user only holds one group at a time
+        self.release_group(user_id)?;
+
+        let mut seen = HashSet::new();
+        ids.retain(|id| seen.insert(*id));
+        if ids.is_empty() || ids.len() > 256 { return Err("Invalid group".into()); }
+
+        let mut poses: Vec<(PawnId, Isometry<f32>)> = vec![];
+        for id in &ids {
+            let pawn = self.pawns.get(id).ok_or("Trying to group invalid pawn")?;
+            if !pawn.moveable || pawn.selected_user.is_some_and(|u| u != user_id) {
+                return Err("Trying to group non-owned pawn".into());
+            }
+            poses.push((pawn.id, Isometry::from_parts(Vector::from(&pawn.position).into(), Rotation::from(&pawn.rotation))));
+        }
+
+        // Pivot around the centroid unless the user grabbed somewhere specific
+        let origin: Vector<f32> = origin.as_ref().map(Vector::from).unwrap_or_else(|| {
+            poses.iter().map(|(_, pose)| pose.translation.vector).sum::<Vector<f32>>() / poses.len() as f32
+        });
+        let pose = Isometry::translation(origin.x, origin.y, origin.z);
+        self.groups.insert(user_id, PawnGroup {
+            pose,
+            offsets: poses.into_iter().map(|(id, p)| (id, pose.inverse() * p)).collect(),
+        });
+
+        self.update_group(user_id, ids.iter().map(|&id| PawnUpdate {
+            id, selected: Some(true), ..Default::default()
+        }).collect())?;
+        self.run_group_callbacks(user_id, &ids, true)
+    }
+    pub fn move_group(&mut self, user_id: UserId, position: Vec3, rotation: Quat) -> Result<(), Box<dyn Error>> {
+        let group = self.groups.get_mut(&user_id).ok_or("User has no group selected")?;
+        group.pose = Isometry::from_parts(Vector::from(&position).into(), Rotation::from(&rotation));
+
+        let updates = group.offsets.iter().map(|(id, offset)| {
+            let pose = group.pose * offset;
+            PawnUpdate {
+                id: *id,
+                position: Some(Vec3::from(&pose.translation.vector)),
+                rotation: Some(Quat::from(&pose.rotation)),
+                ..Default::default()
+            }
+        }).collect();
+        self.update_group(user_id, updates)
+    }
+    pub fn release_group(&mut self, user_id: UserId) -> Result<(), Box<dyn Error>> {
+        let Some(group) = self.groups.remove(&user_id) else { return Ok(()); };
+        let ids: Vec<PawnId> = group.ids().into_iter().filter(|id| self.pawns.contains_key(id)).collect();
+
+        // Only the pawn the group was grabbed by snaps, and the rest keep their places around it
+        let increment = self.info.as_ref().and_then(|i| i.rotation_increment).unwrap_or(FRAC_PI_2) as f32;
+        let anchor = ids.first().and_then(|id| self.pawns.get(id)).filter(|pawn| pawn.moveable);
+        let snap = anchor.and_then(|anchor| {
+            let (snap_point, cell, position) = self.nearest_snap(anchor)?;
+            let from = Isometry::from_parts(Vector::from(&anchor.position).into(), Rotation::from(&anchor.rotation));
+            let to = Isometry::from_parts(Vector::from(&position).into(),
+                                          Rotation::from(&snap_point.snap_rotation(&anchor.rotation, increment)));
+            Some((to * from.inverse(), snap_point.on_snap_callback.clone(), cell, anchor.id))
+        });
+
+        // Release every pawn in one update so they return to dynamic together
+        let updates = ids.iter().filter_map(|id| self.pawns.get(id)).map(|pawn| {
+            let mut update = PawnUpdate { id: pawn.id, selected: Some(false), ..Default::default() };
+            if let Some((delta, ..)) = &snap {
+                let pose = delta * Isometry::from_parts(Vector::from(&pawn.position).into(), Rotation::from(&pawn.rotation));
+                update.position = Some(Vec3::from(&pose.translation.vector));
+                update.rotation = Some(Quat::from(&pose.rotation));
+                update.select_rotation = Some(Quat::from(&(delta.rotation * Rotation::from(&pawn.select_rotation))));
+            }
+            update
+        }).collect();
+        self.update_group(user_id, updates)?;
+
+        if snap.is_some() {
+            // Snapped pawns land where they're put instead of being thrown
+            for rb_handle in ids.iter().filter_map(|id| self.pawns.get(id)?.rigid_body) {
+                if let Some(rb) = self.world.rigid_body_set.get_mut(rb_handle) {
+                    rb.set_linvel(vector![0.0, 0.0, 0.0], true);
+                }
+            }
+        }
+        if let Some((_, Some(callback), (x, y), anchor)) = snap {
+            if let Err(e) = self.lua_scope(|lua, _scope, _| {
+                let cell = Vec2 { x: x as f64, y: y as f64 };
+                lua.registry_value::<mlua::Function>(&callback)?.call::<_, ()>((anchor.0, cell, user_id.0))
+            }) {
+                self.system_chat(Cow::Owned(format!("Lua error in on_snap: `{}`", e)))?;
+            }
+        }
+        self.run_group_callbacks(user_id, &ids, false)
+    }
+    /// Apply updates to a user's group, which the server places, so the user gets the results too
+    fn update_group(&mut self, user_id: UserId, updates: Vec<PawnUpdate>) -> Result<(), Box<dyn Error>> {
+        let (updates, _) = self.apply_pawn_updates(Some(user_id), updates, true)?;
+        self.sync_visibility(Some(&updates.iter().map(|u| u.id).collect::<Vec<_>>()))?;
+        self.send_pawn_updates(None, updates)
+    }
+    /// Run each grouped pawn's `on_grab` or `on_release` once, with the ids of the whole group
+    fn run_group_callbacks(&mut self, user_id: UserId, ids: &[PawnId], grab: bool) -> Result<(), Box<dyn Error>> {
+        let callbacks: Vec<Arc<mlua::RegistryKey>> = ids.iter().filter_map(|id| self.pawns.get(id)).filter_map(|pawn| {
+            if grab { pawn.on_grab_callback.clone() } else { pawn.on_release_callback.clone() }
+        }).collect();
+        let group: Vec<u64> = ids.iter().map(|id| id.0).collect();
+
+        if let Err(e) = self.lua_scope(|lua, _scope, _| {
+            for callback in &callbacks {
+                lua.registry_value::<mlua::Function>(callback)?.call::<_, ()>((user_id.0, group.clone()))?;
+            }
+            Ok(())
+        }) {
+            self.system_chat(Cow::Owned(format!("Lua error in {}: `{}`", if grab { "on_grab" } else { "on_release" }, e)))?;
+        }
+        Ok(())
+    }
+
+    // -- JOINTS --
+
+    /// Whether `user` may attach or detach `pawn`, which the host can always do
+    fn can_attach(&self, pawn: &Pawn, user: UserId) -> bool {
+        user == self.host || (pawn.owner.map_or(true, |owner| owner == user) && self.can_see(pawn, user))
+    }
+    /// Attach two pawns, as a user if `user_id` is set or as the game otherwise
+    pub fn attach_pawns(&mut self, user_id: Option<UserId>, mut joint: Joint) -> Result<JointId, Box<dyn Error>> {
+        if self.joints.len() >= 1024 { return Err("Failed to attach pawns".into()); }
+        if joint.pawns.0 == joint.pawns.1 { return Err("Cannot attach a pawn to itself".into()); }
+
+        let pawn1 = self.pawns.get(&joint.pawns.0).ok_or("Invalid pawn id")?;
+        let pawn2 = self.pawns.get(&joint.pawns.1).ok_or("Invalid pawn id")?;
+        if let Some(user_id) = user_id {
+            if !self.can_attach(pawn1, user_id) || !self.can_attach(pawn2, user_id) {
+                return Err("User attempting to attach pawns they don't control".into());
+            }
+        }
+        let rb_handle1 = pawn1.rigid_body.ok_or("Pawn missing rigidbody")?;
+        let rb_handle2 = pawn2.rigid_body.ok_or("Pawn missing rigidbody")?;
+        let pose1 = *self.world.rigid_body_set.get(rb_handle1).ok_or("Invalid rigidbody handle")?.position();
+        let pose2 = *self.world.rigid_body_set.get(rb_handle2).ok_or("Invalid rigidbody handle")?.position();
+
+        // Ids are always allocated here, so they can't collide with ones handed out to Lua
+        joint.id = self.next_joint_id();
+        joint.handle = Some(self.world.impulse_joint_set.insert(rb_handle1, rb_handle2, joint.build(&pose1, &pose2), true));
+
+        self.users.values().send_event(&Event::AttachPawns { joint: Cow::Borrowed(&joint) })?;
+
+        let id = joint.id;
+        self.joints.insert(id, joint);
+        Ok(id)
+    }
+    pub fn detach_pawns(&mut self, user_id: Option<UserId>, ids: Vec<JointId>) -> Result<(), Box<dyn Error>> {
+        if let Some(user_id) = user_id {
+            let allowed = ids.iter().filter_map(|id| self.joints.get(id)).all(|joint| {
+                [joint.pawns.0, joint.pawns.1].iter()
+                    .all(|id| self.pawns.get(id).is_some_and(|pawn| self.can_attach(pawn, user_id)))
+            });
+            if !allowed { return Err("User attempting to detach pawns they don't control".into()); }
+        }
+        // Only tell clients about joints that existed
+        let ids: Vec<JointId> = ids.into_iter().filter_map(|id| self.joints.remove(&id)).map(|joint| {
+            if let Some(handle) = joint.handle {
+                self.world.impulse_joint_set.remove(handle, true);
+            }
+            joint.id
+        }).collect();
+        if ids.is_empty() { return Ok(()); }
+
+        self.users.values().send_event(&Event::DetachPawns { ids })
+    }
+
+    // -- ZONES --
+
+    pub fn add_zone(&mut self, mut zone: Zone) -> Result<ZoneId, Box<dyn Error>> {
+        if self.zones.len() >= 256 { return Err("Failed to add zone".into()); }
+
+        zone.collider = Some(self.world.collider_set.insert(zone.collider()));
+        let id = zone.id;
+        self.zones.insert(id, zone);
+        Ok(id)
+    }
+    pub fn remove_zone(&mut self, id: ZoneId) -> Option<Zone> {
+        let mut zone = self.zones.remove(&id)?;
+        if let Some(handle) = zone.collider.take() {
+            self.world.remove_collider(handle);
+        }
+        Some(zone)
+    }
+    pub fn update_zones(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut transitions: Vec<(&str, Option<Arc<mlua::RegistryKey>>, PawnId, ZoneId)> = vec![];
+        for zone in self.zones.values_mut() {
+            let Some(handle) = zone.collider else { continue; };
+            let inside: HashSet<PawnId> = self.world.narrow_phase.intersection_pairs_with(handle)
+                .filter(|&(_, _, intersecting)| intersecting)
+                .filter_map(|(a, b, _)| self.world.collider_set.get(if a == handle { b } else { a })?.parent())
+                .filter_map(|rb_handle| self.world.rigid_body_set.get(rb_handle))
+                .map(|rb| PawnId(rb.user_data as u64))
+                .filter(|id| self.pawns.contains_key(id))
+                .collect();
+
+            for &id in zone.contents.difference(&inside) {
+                transitions.push(("on_exit", zone.on_exit_callback.clone(), id, zone.id));
+            }
+            for &id in inside.difference(&zone.contents) {
+                transitions.push(("on_enter", zone.on_enter_callback.clone(), id, zone.id));
+            }
+            zone.contents = inside;
+        }
+
+        for (name, callback, pawn_id, zone_id) in transitions {
+            let Some(callback) = callback else { continue; };
+            if let Err(e) = self.lua_scope(|lua, _scope, _| {
+                lua.registry_value::<mlua::Function>(&callback)?.call::<_, ()>((pawn_id.0, zone_id.0))
+            }) {
+                self.system_chat(Cow::Owned(format!("Lua error in {}: `{}`", name, e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    // -- SPATIAL QUERIES --
+
+    fn pawn_for_collider(&self, handle: ColliderHandle) -> Option<&Pawn> {
+        let rb_handle = self.world.collider_set.get(handle)?.parent()?;
+        let rb = self.world.rigid_body_set.get(rb_handle)?;
+        self.pawns.get(&PawnId(rb.user_data as u64))
+    }
+    pub fn raycast(&mut self, origin: Vec3, direction: Vec3, max_distance: f32, filter: &PawnFilter) -> Result<Option<RaycastHit>, Box<dyn Error>> {
+        let direction = Vector::from(&direction);
+        if !direction.norm().is_normal() { return Err("Raycast direction must be non-zero".into()); }
+        if max_distance.is_nan() || max_distance < 0. { return Err("Raycast distance must be positive".into()); }
+        self.world.update_query_pipeline();
+
+        let ray = Ray::new(Vector::from(&origin).into(), direction.normalize());
+        let predicate = |handle, _: &Collider| self.pawn_for_collider(handle).is_some_and(|p| filter.matches(p));
+        let Some((handle, distance)) = self.world.query_pipeline.cast_ray(
+            &self.world.rigid_body_set, &self.world.collider_set,
+            &ray, max_distance, true, QueryFilter::new().predicate(&predicate)
+        ) else { return Ok(None); };
+        Ok(self.pawn_for_collider(handle).map(|pawn| (pawn.id, Vec3::from(&ray.point_at(distance).coords), distance)))
+    }
+    pub fn pawns_overlapping(&mut self, position: &Isometry<f32>, shape: &dyn Shape, filter: &PawnFilter) -> Vec<PawnId> {
+        self.world.update_query_pipeline();
+
+        let predicate = |handle, _: &Collider| self.pawn_for_collider(handle).is_some_and(|p| filter.matches(p));
+        let mut ids: Vec<PawnId> = vec![];
+        self.world.query_pipeline.intersections_with_shape(
+            &self.world.rigid_body_set, &self.world.collider_set,
+            position, shape, QueryFilter::new().predicate(&predicate),
+            |handle| {
+                if let Some(pawn) = self.pawn_for_collider(handle) {
+                    if !ids.contains(&pawn.id) { ids.push(pawn.id); } // Pawns may have several colliders
+                }
+                true
+            }
+        );
+        ids.sort_by_key(|id| id.0);
+        ids
+    }
+    pub fn nearest_pawn(&mut self, position: Vec3, max_distance: f32, filter: &PawnFilter) -> Option<PawnId> {
+        self.world.update_query_pipeline();
+
+        let point: Point<f32> = Vector::from(&position).into();
+        let predicate = |handle, _: &Collider| self.pawn_for_collider(handle).is_some_and(|p| filter.matches(p));
+        let (handle, projection) = self.world.query_pipeline.project_point(
+            &self.world.rigid_body_set, &self.world.collider_set,
+            &point, true, QueryFilter::new().predicate(&predicate)
+        )?;
+        if (projection.point - point).norm() > max_distance { return None; }
+        Some(self.pawn_for_collider(handle)?.id)
+    }
+
+    // -- VISIBILITY --
+
+    pub fn reveal_pawn(&mut self, id: PawnId, user_ids: Vec<UserId>) -> Result<(), Box<dyn Error>> {
+        let pawn = self.pawns.get_mut(&id).ok_or("Trying to reveal missing pawn")?;
+        pawn.revealed_to.extend(user_ids);
+
+        let update = PawnUpdate {
+            id,
+            data: Some(pawn.data.clone()),
+            ..Default::default()
+        };
+        self.send_pawn_updates(None, vec![update])
+    }
+    /// Whether `user` is allowed to know `pawn` exists, taking hidden zones into account
+    pub fn can_see(&self, pawn: &Pawn, user: UserId) -> bool {
+        Self::can_see_among(pawn, user, &self.hidden_zones())
+    }
+    fn hidden_zones(&self) -> Vec<&Pawn> {
+        self.pawns.values().filter(|p| matches!(p.data, PawnData::HiddenZone { .. })).collect()
+    }
+    fn can_see_among(pawn: &Pawn, user: UserId, zones: &[&Pawn]) -> bool {
+        pawn.visible_to_user(user) && !zones.iter().any(|zone| {
+            zone.id != pawn.id && zone.owner != Some(user) && zone.zone_contains(&pawn.position)
+        })
+    }
+    /// Add or remove pawns on clients whose visibility has changed since it was last synced.
+    /// Only `changed` pawns are rechecked (or every pawn with `None`), unless a hidden zone among them
+    /// moved, which can change the visibility of anything inside it.
+    pub fn sync_visibility(&mut self, changed: Option<&[PawnId]>) -> Result<(), Box<dyn Error>> {
+        let restricted = self.pawns.values().any(|p| {
+            p.visible_to.is_some() || !p.hidden_from.is_empty() ||
+                matches!(p.data, PawnData::HiddenZone { .. })
+        });
+        if !restricted { return Ok(()); }
+
+        let zones = self.hidden_zones();
+        let changed = changed.filter(|ids| !ids.iter().any(|id| zones.iter().any(|zone| zone.id == *id)));
+        let pawns: Vec<&Pawn> = match changed {
+            Some(ids) => ids.iter().filter_map(|id| self.pawns.get(id)).collect(),
+            None => self.pawns.values().collect(),
+        };
+        let changes: Vec<(PawnId, HashSet<UserId>)> = pawns.into_iter().filter_map(|pawn| {
+            let hidden_from: HashSet<UserId> = self.users.keys().filter(|&&id| !Self::can_see_among(pawn, id, &zones)).copied().collect();
+            (hidden_from != pawn.hidden_from).then_some((pawn.id, hidden_from))
+        }).collect();
+
+        for (id, hidden_from) in changes {
+            let pawn = self.pawns.get_mut(&id).unwrap();
+            let shown: Vec<UserId> = pawn.hidden_from.difference(&hidden_from).copied().collect();
+            let hidden: Vec<UserId> = hidden_from.difference(&pawn.hidden_from).copied().collect();
+            pawn.hidden_from = hidden_from;
+
+            let pawn = &self.pawns[&id];
+            for user in shown.iter().filter_map(|id| self.users.get(id)) {
+                user.send_event(&Event::AddPawn { pawn: pawn.redacted_for(user.id) })?;
+            }
+            for user in hidden.iter().filter_map(|id| self.users.get(id)) {
+                user.send_event(&Event::RemovePawns { ids: vec![id] })?;
+            }
+        }
+        Ok(())
+    }
+    fn send_add_pawn(&self, pawn: &Pawn) -> Result<(), Box<dyn Error>> {
+        if !pawn.concealable() && pawn.hidden_from.is_empty() {
+            return self.users.values().send_event(&Event::AddPawn { pawn: Cow::Borrowed(pawn) });
+        }
+        for user in self.users.values().filter(|u| !pawn.hidden_from.contains(&u.id)) {
+            user.send_event(&Event::AddPawn { pawn: pawn.redacted_for(user.id) })?;
+        }
+        Ok(())
+    }
+    /// Relay pawn updates to every user except `source`, skipping pawns hidden from a user and
+    /// redacting concealable pawns per recipient. `source` still receives the data of concealable pawns
+    /// they updated, since flipping or shuffling can change which faces they're allowed to see.
+    pub fn send_pawn_updates(&self, source: Option<UserId>, updates: Vec<PawnUpdate>) -> Result<(), Box<dyn Error>> {
+        let concealed = |update: &PawnUpdate| {
+            (update.data.is_some() || update.select_rotation.is_some()) &&
+                self.pawns.get(&update.id).is_some_and(|p| p.concealable())
+        };
+        let hidden = |update: &PawnUpdate, user: UserId| {
+            self.pawns.get(&update.id).is_some_and(|p| p.hidden_from.contains(&user))
+        };
+        let restricted = |update: &PawnUpdate| {
+            concealed(update) || self.pawns.get(&update.id).is_some_and(|p| !p.hidden_from.is_empty())
+        };
+        if !updates.iter().any(restricted) {
+            return self.users.values()
+                .filter(|u| source != Some(u.id))
+                .send_event(&Event::UpdatePawns { updates, collisions: None, tick: Some(self.tick) });
+        }
+
+        for user in self.users.values() {
+            let is_source = source == Some(user.id);
+            let user_updates: Vec<PawnUpdate> = updates.iter().filter_map(|update| {
+                if hidden(update, user.id) {
+                    return None;
+                }
+                if !concealed(update) {
+                    return (!is_source).then(|| update.clone());
+                }
+                let mut update = if is_source {
+                    PawnUpdate { id: update.id, ..Default::default() }
+                } else {
+                    update.clone()
+                };
+                update.data = Some(self.pawns[&update.id].data_for(user.id).into_owned());
+                Some(update)
+            }).collect();
+
+            if !user_updates.is_empty() {
+                user.send_event(&Event::UpdatePawns { updates: user_updates, collisions: None, tick: Some(self.tick) })?;
+            }
+        }
+        Ok(())
+    }
+
     // -- USER STATUS EVENTS --
 
     pub fn update_user(&mut self, user_id: UserId, updates: Vec<UserStatusUpdate>) -> Result<(), Box<dyn Error>> {
@@ -831,42 +1833,78 @@ impl Lobby {
         println!("User <{user_id:?}> registering game \"{}\" for lobby [{}]",
                 info.name, self.name);
 
-        self.info = Some(info.into_owned());
+        let assets = self.register_assets(user_id, assets)?;
+        self.start_game(info.into_owned(), assets)
+    }
+    /// Start a plugin from the server's library, without the host uploading its assets.
+    /// The server reads these off the lobby lock instead, see `register_plugin` in `src/server.rs`
+    pub fn register_plugin(&mut self, user_id: UserId, id: &str) -> Result<(), Box<dyn Error>> {
+        if user_id != self.host { return Err("Failed to register plugin".into()); }
 
-        self.register_assets(user_id, assets)?;
+        let plugin = self.library.load(id)?;
+        self.install_plugin(user_id, plugin)
+    }
+    /// Start an already read plugin, e.g. one uploaded as a zip
+    pub fn install_plugin(&mut self, user_id: UserId, plugin: Plugin) -> Result<(), Box<dyn Error>> {
+        if user_id != self.host { return Err("Failed to install plugin".into()); }
+
+        println!("User <{user_id:?}> installing \"{}\" ({} assets) for lobby [{}]",
+                plugin.info.name, plugin.files.len(), self.name);
+        let info = plugin.info.clone();
+        self.start_game(info, plugin.into_assets())
+    }
+    fn start_game(&mut self, info: GameInfo, assets: HashMap<String, Asset>) -> Result<(), Box<dyn Error>> {
+        // Scripts may still change this when the game starts
+        let environment = info.environment.clone().unwrap_or_default();
+        environment.validate()?;
+        self.info = Some(info);
+        self.world.configure(environment);
+
+        self.install_assets(assets)?;
 
         self.users.values()
             .send_event(&Event::RegisterGame {
                 info: Cow::Borrowed(self.info.as_ref().ok_or("Lobby missing GameInfo")?),
-                assets: HashMap::default()
+                assets: self.asset_versions()
             })
     }
-    pub fn register_assets(&mut self, user_id: UserId, assets: HashMap<String, String>) -> Result<(), Box<dyn Error>> {
-        if user_id != self.host || self.assets.len() >= 256 { return Err("Failed to register asset".into()); }
+    /// Each asset's path (without the leading `/`) and version, for clients to build asset URLs
+    pub fn asset_versions(&self) -> HashMap<String, String> {
+        self.assets.iter().map(|(path, asset)| (path.trim_start_matches('/').to_string(), asset.version())).collect()
+    }
+    /// Decode uploaded data URLs, checking them against the asset limits
+    pub fn register_assets(&mut self, user_id: UserId, assets: HashMap<String, String>) -> Result<HashMap<String, Asset>, Box<dyn Error>> {
+        if user_id != self.host || assets.len() > MAX_ASSETS { return Err("Failed to register asset".into()); }
 
         println!("User <{user_id:?}> registering assets for lobby [{}]:", self.name);
         let mut processed_assets: HashMap<String, Asset> = HashMap::new();
         for (name, data) in assets.into_iter() {
-            if processed_assets.values().fold(0, |acc, a| acc + a.data.len()) > 1024 * 1024 * 40 { return Err("Attempting to register >40 MiB of assets".into()); }
+            if processed_assets.values().fold(0, |acc, a| acc + a.data.len()) > MAX_ASSETS_SIZE { return Err("Attempting to register >40 MiB of assets".into()); }
             if processed_assets.get(&name).is_some() { return Err("Attempting to overwrite asset".into()); }
         
             let url = DataUrl::process(&data).ok().ok_or("Failed to process base64")?;
-            let asset = Asset {
-                mime_type: format!("{}/{}", url.mime_type().type_, url.mime_type().subtype),
-                data: url.decode_to_vec().ok().ok_or("Failed to decode base64")?.0, // Vec<u8>
-            };
-        
-            // No assets above 2 MiB
-            if asset.data.len() > 1024 * 1024 * 2 { return Err("Asset too large".into()); }
+            let declared = format!("{}/{}", url.mime_type().type_, url.mime_type().subtype);
+            let data = url.decode_to_vec().ok().ok_or("Failed to decode base64")?.0; // Vec<u8>
+
+            // No assets above 2 MiB, checked before they're sniffed or shared with other lobbies
+            if data.len() > MAX_ASSET_SIZE { return Err("Asset too large".into()); }
+            let asset = Asset::new(check_asset(&name, Some(&declared), &data)?.to_string(), data);
 
             processed_assets.insert(name.to_string(), asset);
         
             println!(" - \"{name}\"");
         }
+        Ok(processed_assets)
+    }
+    /// Replace the lobby's assets, restarting the game if they include a `main.lua`
+    pub fn install_assets(&mut self, processed_assets: HashMap<String, Asset>) -> Result<(), Box<dyn Error>> {
         println!(" - Asset count: {} | Total size: {} KiB",
                 processed_assets.len(),
                 processed_assets.values().fold(0, |acc, a| acc + a.data.len())/1024);
 
+        // Uploaded meshes may have changed
+        self.collider_warnings.clear();
+
         // Load lua if it exists
         // `require` function is only defined on initial load.
         if processed_assets.contains_key("/main.lua") {
@@ -877,7 +1915,7 @@ impl Lobby {
             if let Err(e) = self.lua_scope(|lua, scope, _| {
                 lua.globals().set("require", scope.create_function(|lua, path: String| {
                     let chunk = if let Some(asset) = processed_assets.get(&format!("/{}.lua", path)) {
-                        Some(String::from_utf8(asset.data.clone()).unwrap())
+                        Some(String::from_utf8(asset.data.to_vec()).unwrap())
                     } else {
                         LUA_DIR.get_file(format!("{path}.lua"))
                             .and_then(|file| file.contents_utf8()).map(|text| text.to_string())
@@ -937,4 +1975,345 @@ impl Lobby {
         self.users.get(&user_id).ok_or("Invalid user id")?.send_event(&Event::Pong { idx })?;
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A lobby with one pawn sliding across the table, and `script` loaded
+    fn moving_lobby(script: &str) -> (Lobby, RigidBodyHandle) {
+        let mut lobby = Lobby::new();
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load("lobby:create_pawn({ position = { x = 0, y = 5, z = 0 } })").exec()?;
+            lua.load(script).exec()
+        }).unwrap();
+
+        let handle = lobby.pawns.values().next().unwrap().rigid_body.unwrap();
+        lobby.world.rigid_body_set[handle].set_linvel(vector![200., 0., 0.], true);
+        (lobby, handle)
+    }
+    fn lua_global<T: for<'lua> mlua::FromLua<'lua>>(lobby: &mut Lobby, name: &str) -> T {
+        lobby.lua_scope(|lua, _scope, _| lua.globals().get::<_, T>(name)).unwrap()
+    }
+
+    #[test]
+    fn timers_fire_while_bodies_move() {
+        let (mut lobby, handle) = moving_lobby(r#"
+            ticks = 0
+            fired_at = nil
+            function game.physics() ticks = ticks + 1 end
+            lobby:timeout(function() fired_at = ticks end, 10)
+        "#);
+
+        for tick in 1..=20 {
+            lobby.step().unwrap();
+            assert!(lobby.world.rigid_body_set[handle].is_moving(), "pawn stopped moving on tick {}", tick);
+        }
+
+        // Timers run before game.physics within a tick
+        assert_eq!(lua_global::<Option<u32>>(&mut lobby, "fired_at"), Some(9));
+        assert_eq!(lua_global::<u32>(&mut lobby, "ticks"), 20);
+    }
+
+    #[test]
+    fn timers_fire_in_scheduled_order() {
+        let (mut lobby, _) = moving_lobby(r#"
+            order = {}
+            for i = 1, 8 do
+                lobby:timeout(function() table.insert(order, i) end, 3)
+            end
+            lobby:timeout(function() table.insert(order, 0) end, 1)
+            lobby:timeout(function()
+                lobby:timeout(function() table.insert(order, 9) end, 0)
+            end, 3)
+        "#);
+
+        for _ in 0..3 {
+            lobby.step().unwrap();
+        }
+        assert_eq!(lua_global::<Vec<u32>>(&mut lobby, "order"), vec![0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // Timers scheduled by a timer wait for the next tick
+        lobby.step().unwrap();
+        assert_eq!(lua_global::<Vec<u32>>(&mut lobby, "order"), vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert!(lobby.scheduled_lua_funcs.is_empty());
+    }
+
+    #[test]
+    fn timeout_seconds_rounds_up_to_ticks() {
+        let (mut lobby, _) = moving_lobby(r#"
+            fired_on = nil
+            lobby:timeout_seconds(function() fired_on = lobby:tick() end, 0.1)
+        "#);
+
+        for _ in 0..10 {
+            lobby.step().unwrap();
+        }
+        // 0.1 seconds is 4.5 ticks at 45Hz
+        assert_eq!(lua_global::<Option<u64>>(&mut lobby, "fired_on"), Some(5));
+        assert_eq!(lobby.tick, 10);
+    }
+
+    // A lobby with a concealable deck of cards "1" to "20", face up
+    fn deck_lobby() -> (Lobby, PawnId) {
+        let mut lobby = Lobby::new();
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load(r#"
+                local contents = {}
+                for i = 1, 20 do contents[i] = tostring(i) end
+                lobby:create_pawn({ data = DeckData:new({ contents = contents, back = "back" }) })
+            "#).exec()
+        }).unwrap();
+
+        let id = *lobby.pawns.keys().next().unwrap();
+        (lobby, id)
+    }
+    fn deck_contents(data: &PawnData) -> Vec<String> {
+        match data {
+            PawnData::Deck { contents, .. } => contents.clone(),
+            _ => panic!("Not a deck"),
+        }
+    }
+
+    #[test]
+    fn face_up_decks_show_only_their_top_card() {
+        let (mut lobby, id) = deck_lobby();
+        let user = UserId(1);
+
+        // The client renders `contents[0]` on the face of the deck (see `Deck` in static/js/containers.js)
+        let seen = deck_contents(&lobby.pawns[&id].data_for(user));
+        assert_eq!(seen[0], "1");
+        assert!(seen[1..].iter().all(|card| card == "back"));
+
+        // Flipped over, the top card is face down too
+        lobby.pawns.get_mut(&id).unwrap().select_rotation = Quat { x: 1., y: 0., z: 0., w: 0. };
+        assert!(deck_contents(&lobby.pawns[&id].data_for(user)).iter().all(|card| card == "back"));
+    }
+
+    #[test]
+    fn decks_only_shuffle_when_asked() {
+        let (mut lobby, id) = deck_lobby();
+        let user = UserId(1);
+        let contents = deck_contents(&lobby.pawns[&id].data);
+
+        // Clients send back their redacted view, which mustn't reorder or replace the deck
+        let seen = lobby.pawns[&id].data_for(user).into_owned();
+        lobby.update_pawns(Some(user), vec![PawnUpdate { id, data: Some(seen.clone()), ..Default::default() }]).unwrap();
+        assert_eq!(deck_contents(&lobby.pawns[&id].data), contents);
+
+        lobby.update_pawns(Some(user), vec![PawnUpdate { id, data: Some(seen), shuffle: Some(true), ..Default::default() }]).unwrap();
+        let shuffled = deck_contents(&lobby.pawns[&id].data);
+        assert_ne!(shuffled, contents);
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        let mut expected = contents.clone();
+        expected.sort();
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn hidden_pawns_ignore_updates() {
+        let mut lobby = Lobby::new();
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load("lobby:create_pawn({ position = { x = 0, y = 5, z = 0 }, visible_to = { 2 } })").exec()
+        }).unwrap();
+        let id = *lobby.pawns.keys().next().unwrap();
+
+        let moved = PawnUpdate { id, position: Some(Vec3 { x: 10., y: 5., z: 0. }), ..Default::default() };
+        lobby.update_pawns(Some(UserId(1)), vec![moved.clone()]).unwrap();
+        assert_eq!(lobby.pawns[&id].position.x, 0.);
+
+        lobby.update_pawns(Some(UserId(2)), vec![moved]).unwrap();
+        assert_eq!(lobby.pawns[&id].position.x, 10.);
+    }
+
+    #[test]
+    fn rejected_discards_stay_in_hand() {
+        let (mut lobby, deck) = deck_lobby();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let user = lobby.add_user(tx);
+        lobby.lua_scope(|lua, _scope, _| lua.load("lobby:create_pawn({})").exec()).unwrap();
+        let pawn = *lobby.pawns.keys().find(|id| **id != deck).unwrap();
+        lobby.store_pawn(None, pawn, PawnOrUser::User(user)).unwrap();
+
+        assert!(lobby.discard_pawn(user, user, pawn, deck).is_err());
+        assert!(lobby.users[&user].hand.contains_key(&pawn));
+        assert_eq!(deck_contents(&lobby.pawns[&deck].data).len(), 20);
+    }
+
+    #[test]
+    fn hidden_pawns_cant_be_taken_or_removed() {
+        let (mut lobby, deck) = deck_lobby();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let user = lobby.add_user(tx);
+        let (tx, _rx2) = mpsc::unbounded_channel();
+        let other = lobby.add_user(tx);
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load(format!("lobby:create_pawn(Pawn:new {{ rotation = quat(0, 0, 0, 1), owner = {}, data = HiddenZoneData:new {{ size = vec3(10, 10, 10) }} }})", other.0)).exec()
+        }).unwrap();
+
+        assert!(lobby.store_pawn(Some(user), deck, PawnOrUser::User(user)).is_err());
+        assert!(lobby.extract_pawns(user, deck, PawnId(1234), Some(user), None).is_err());
+        assert!(lobby.remove_pawns(Some(user), vec![deck]).is_err());
+        assert!(lobby.users[&user].hand.is_empty());
+        assert_eq!(deck_contents(&lobby.pawns[&deck].data).len(), 20);
+
+        // The zone's owner still can
+        lobby.extract_pawns(other, deck, PawnId(1234), Some(other), None).unwrap();
+        assert!(lobby.users[&other].hand.contains_key(&PawnId(1234)));
+    }
+
+    #[test]
+    fn raycasts_need_a_direction_and_distance() {
+        let mut lobby = Lobby::new();
+        let (origin, down) = (Vec3 { x: 0., y: 10., z: 0. }, Vec3 { x: 0., y: -1., z: 0. });
+        let filter = PawnFilter::default();
+        assert!(lobby.raycast(origin.clone(), down.clone(), 100., &filter).is_ok());
+        assert!(lobby.raycast(origin.clone(), Vec3 { x: 0., y: 0., z: 0. }, 100., &filter).is_err());
+        assert!(lobby.raycast(origin.clone(), down.clone(), -1., &filter).is_err());
+        assert!(lobby.raycast(origin, down, f32::NAN, &filter).is_err());
+
+        let result = lobby.lua_scope(|lua, _scope, _| lua.load("lobby:raycast(vec3(0, 10, 0), vec3(0, 0, 0))").exec());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn environments_need_room_for_the_table() {
+        let mut lobby = Lobby::new();
+        let mut set = |environment: &str| lobby.lua_scope(|lua, _scope, _| {
+            lua.load(format!("lobby:set_environment({})", environment)).exec()
+        });
+        assert!(set("{ extents = vec2(20, 20), ground = -5, ceiling = 50 }").is_ok());
+        assert!(set("{ extents = vec2(0, 20) }").is_err());
+        assert!(set("{ extents = vec2(-20, 20) }").is_err());
+        assert!(set("{ ground = 10, ceiling = 10 }").is_err());
+        assert!(set("{ ceiling = 0/0 }").is_err());
+    }
+
+    #[test]
+    fn joints_need_control_of_both_pawns() {
+        let mut lobby = Lobby::new();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let user = lobby.add_user(tx);
+        let (tx, _rx2) = mpsc::unbounded_channel();
+        let other = lobby.add_user(tx);
+        lobby.host = other;
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load(format!("
+                lobby:create_pawn({{ name = 'a' }})
+                lobby:create_pawn({{ name = 'b' }})
+                lobby:create_pawn({{ name = 'c', owner = {} }})
+            ", other.0)).exec()
+        }).unwrap();
+        let named = |name: &str| lobby.pawns.values().find(|p| p.name.as_deref() == Some(name)).unwrap().id;
+        let (a, b, c) = (named("a"), named("b"), named("c"));
+        let joint = |pawns| Joint { id: JointId(0), pawns, kind: JointKind::Fixed, anchor: None, handle: None };
+
+        assert!(lobby.attach_pawns(Some(user), joint((a, c))).is_err());
+        let id = lobby.attach_pawns(Some(user), joint((a, b))).unwrap();
+        assert_ne!(id, JointId(0));
+
+        lobby.remove_pawns(None, vec![b]).unwrap();
+        assert!(lobby.joints.is_empty());
+        assert_eq!(lobby.world.impulse_joint_set.len(), 0);
+    }
+
+    #[test]
+    fn groups_snap_by_their_anchor() {
+        let mut lobby = Lobby::new();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let user = lobby.add_user(tx);
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load("
+                local up = quat(0, 0, 0, 1)
+                lobby:create_pawn(Pawn:new({ rotation = up, moveable = false,
+                                             data = SnapPointData:new({ size = vec2(1, 1), radius = 1, scale = 1 }) }))
+                lobby:create_pawn(Pawn:new({ name = 'a', rotation = up, position = vec3(0.4, 2, 0) }))
+                lobby:create_pawn(Pawn:new({ name = 'b', rotation = up, position = vec3(3.4, 2, 0) }))
+            ").exec()
+        }).unwrap();
+        let named = |name: &str| lobby.pawns.values().find(|p| p.name.as_deref() == Some(name)).unwrap().id;
+        let (a, b) = (named("a"), named("b"));
+
+        lobby.grab_group(user, vec![a, b], None).unwrap();
+        assert!(lobby.pawns[&b].selected_user == Some(user));
+        lobby.release_group(user).unwrap();
+
+        assert!((lobby.pawns[&a].position.x - 0.).abs() < 1e-4);
+        assert!((lobby.pawns[&b].position.x - 3.).abs() < 1e-4);
+        assert!(lobby.pawns[&b].selected_user.is_none());
+
+        // Destroyed pawns leave the group straight away
+        lobby.grab_group(user, vec![a, b], None).unwrap();
+        lobby.remove_pawns(None, vec![b]).unwrap();
+        assert_eq!(lobby.groups[&user].ids(), vec![a]);
+    }
+
+    #[test]
+    fn physics_updates_reset_unset_values() {
+        let mut lobby = Lobby::new();
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load("lobby:create_pawn(Pawn:new({ rotation = quat(0, 0, 0, 1), physics = { linear_damping = 5, mass = 0/0 } }))").exec()
+        }).unwrap();
+        let id = *lobby.pawns.keys().next().unwrap();
+        let damping = |lobby: &Lobby| lobby.world.rigid_body_set[lobby.pawns[&id].rigid_body.unwrap()].linear_damping();
+        assert_eq!(damping(&lobby), 5.);
+        assert!(lobby.world.rigid_body_set[lobby.pawns[&id].rigid_body.unwrap()].mass().is_finite());
+
+        // Clients can't change physical properties
+        let physics = PhysicsProperties { linear_damping: Some(50.), ..Default::default() };
+        lobby.update_pawns(Some(UserId(1)), vec![PawnUpdate { id, physics: Some(physics), ..Default::default() }]).unwrap();
+        assert_eq!(damping(&lobby), 5.);
+
+        lobby.update_pawns(None, vec![PawnUpdate { id, physics: Some(PhysicsProperties::default()), ..Default::default() }]).unwrap();
+        assert_eq!(damping(&lobby), 1.);
+
+        // GLTF extras use the same names as Lua
+        let extras: PhysicsProperties = serde_json::from_str(r#"{ "collider": "box", "linear_damping": 2, "gravity_scale": 0 }"#).unwrap();
+        assert_eq!((extras.linear_damping, extras.gravity_scale), (Some(2.), Some(0.)));
+    }
+
+    #[test]
+    fn failed_mesh_swaps_keep_the_pawn() {
+        let mut lobby = Lobby::new();
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load("lobby:create_pawn(Pawn:new({ rotation = quat(0, 0, 0, 1) }))").exec()
+        }).unwrap();
+        let id = *lobby.pawns.keys().next().unwrap();
+
+        let update = PawnUpdate { id, mesh: Some("missing.gltf".into()), ..Default::default() };
+        lobby.update_pawns(None, vec![update]).unwrap();
+        let rb_handle = lobby.pawns[&id].rigid_body.unwrap();
+        assert!(lobby.world.rigid_body_set.get(rb_handle).is_some());
+    }
+
+    #[test]
+    fn uploaded_colliders_are_freed_with_their_asset() {
+        let mut lobby = Lobby::new();
+        let model = std::fs::read("plugins/catan/pieces/road.gltf").unwrap();
+        lobby.assets.insert("/road.gltf".into(), Asset::new("model/gltf+json".into(), model));
+        lobby.lua_scope(|lua, _scope, _| {
+            lua.load("lobby:create_pawn(Pawn:new({ rotation = quat(0, 0, 0, 1), mesh = 'road.gltf' }))").exec()
+        }).unwrap();
+
+        let data = Arc::downgrade(&lobby.assets["/road.gltf"].data);
+        let hash = lobby.assets["/road.gltf"].hash;
+        assert!(!data.upgrade().unwrap().colliders.lock().unwrap().is_empty());
+        drop(lobby);
+        assert!(data.upgrade().is_none());
+        assert!(!ASSET_STORE.lock().unwrap().contains_key(&hash));
+    }
+
+    #[test]
+    fn physics_callbacks_keep_lobbies_awake_unless_opted_out() {
+        let mut lobby = Lobby::new();
+        assert!(lobby.is_idle());
+
+        lobby.lua_scope(|lua, _scope, _| lua.load("function game.physics() end").exec()).unwrap();
+        assert!(!lobby.is_idle());
+
+        lobby.lua_scope(|lua, _scope, _| lua.load("game.physics_while_idle = false").exec()).unwrap();
+        assert!(lobby.is_idle());
+    }
 }
\ No newline at end of file
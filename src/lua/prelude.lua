@@ -73,6 +73,9 @@ end
 function PawnProxy:destroy()
     lobby:destroy_pawn(self.id)
 end
+function PawnProxy:reveal(user)
+    lobby:reveal_pawn(self.id, user)
+end
 function PawnProxy:new(id)
     local o = {id = id}
     setmetatable(o, self)
@@ -96,6 +99,7 @@ DeckData = {}
 SnapPointData = {}
 ContainerData = {}
 DiceData = {}
+HiddenZoneData = {}
 function DeckData:new(options)
     local o = {
         back = options.back or nil,
@@ -137,6 +141,14 @@ function DiceData:new(options)
     return o
 end
 
+function HiddenZoneData:new(options)
+    local o = {
+        size = options.size or vec3(1, 1, 1),
+    }
+    setmetatable(o, self)
+    return o
+end
+
 -- Lobby extensions
 
 function lobby_ext:schedule(co)
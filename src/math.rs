@@ -80,6 +80,11 @@ pub struct Quat {
         pub z: f64,
         pub w: f64,
 }
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat { x: 0., y: 0., z: 0., w: 1. }
+    }
+}
 impl<'lua> mlua::IntoLua<'lua> for Quat {
     fn into_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
         lua.globals().get::<_, mlua::Table>("quat")?.call((self.x, self.y, self.z, self.w))
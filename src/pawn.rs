@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use serde::{Serialize, Deserialize};
@@ -7,6 +9,7 @@ use rapier3d::prelude::*;
 
 use crate::user::*;
 use crate::math::*;
+use crate::physics::PhysicsProperties;
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "class", content = "data")]
@@ -20,6 +23,7 @@ pub enum PawnData {
     Container { holds: Box<Pawn>, capacity: Option<u64> },
     #[serde(rename_all = "camelCase")]
     Dice { roll_rotations: Vec<Quat> },
+    HiddenZone { size: Vec3 },
     Pawn {},
 }
 impl<'lua> mlua::FromLua<'lua> for PawnData {
@@ -30,6 +34,7 @@ impl<'lua> mlua::FromLua<'lua> for PawnData {
                 let snap_point_mt = lua.globals().get::<_, mlua::Table>("SnapPointData")?;
                 let container_mt = lua.globals().get::<_, mlua::Table>("ContainerData")?;
                 let dice_mt = lua.globals().get::<_, mlua::Table>("DiceData")?;
+                let hidden_zone_mt = lua.globals().get::<_, mlua::Table>("HiddenZoneData")?;
                 if mt == deck_mt {
                     Ok(PawnData::Deck {
                         contents: table.get("contents")?,
@@ -54,6 +59,8 @@ impl<'lua> mlua::FromLua<'lua> for PawnData {
                     })
                 } else if mt == dice_mt {
                     Ok(PawnData::Dice { roll_rotations: table.get("roll_rotations")? })
+                } else if mt == hidden_zone_mt {
+                    Ok(PawnData::HiddenZone { size: table.get("size")? })
                 } else {
                     Err(mlua::Error::FromLuaConversionError { from: "table", to: "PawnData", message: Some("Invalid PawnData type".to_string()) })
                 }
@@ -68,22 +75,98 @@ impl<'lua> mlua::FromLua<'lua> for PawnData {
         }
     }
 }
-impl TryInto<Collider> for &PawnData {
+impl<'lua> mlua::IntoLua<'lua> for PawnData {
+    fn into_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        let table = lua.create_table()?;
+        let data_type = match self {
+            PawnData::Deck { contents, back, side_color, border, corner_radius, card_thickness, size } => {
+                table.set("contents", contents)?;
+                table.set("back", back)?;
+                table.set("side_color", side_color)?;
+                table.set("border", border)?;
+                table.set("corner_radius", corner_radius)?;
+                table.set("card_thickness", card_thickness)?;
+                table.set("size", size)?;
+                "DeckData"
+            },
+            PawnData::SnapPoint { radius, size, scale, snaps } => {
+                table.set("radius", radius)?;
+                table.set("size", size)?;
+                table.set("scale", scale)?;
+                table.set("snaps", snaps)?;
+                "SnapPointData"
+            },
+            PawnData::Container { holds, capacity } => {
+                table.set("holds", *holds)?;
+                table.set("capacity", capacity)?;
+                "ContainerData"
+            },
+            PawnData::Dice { roll_rotations } => {
+                table.set("roll_rotations", roll_rotations)?;
+                "DiceData"
+            },
+            PawnData::HiddenZone { size } => {
+                table.set("size", size)?;
+                "HiddenZoneData"
+            },
+            PawnData::Pawn {} => return Ok(mlua::Value::Nil),
+        };
+
+        table.set_metatable(Some(lua.globals().get::<_, mlua::Table>(data_type)?));
+        Ok(mlua::Value::Table(table))
+    }
+}
+impl TryInto<ColliderBuilder> for &PawnData {
     type Error = ();
 
-    fn try_into(self) -> Result<Collider, Self::Error> {
+    fn try_into(self) -> Result<ColliderBuilder, Self::Error> {
         match &self {
             PawnData::Deck { contents, card_thickness, size, .. } => {
                 Ok(ColliderBuilder::cuboid(size.x as f32/2.,
                                     ((*card_thickness as f32 * contents.len() as f32 * 1.15)/2.).max(0.03),
-                                    size.y as f32/2.)
-                    .friction(0.7).mass(0.01)
-                    .active_events(ActiveEvents::COLLISION_EVENTS).build())
+                                    size.y as f32/2.))
             },
             _ => Err(())
         }
     }
 }
+impl PawnData {
+    pub fn class(&self) -> &'static str {
+        match self {
+            PawnData::Deck { .. } => "Deck",
+            PawnData::SnapPoint { .. } => "SnapPoint",
+            PawnData::Container { .. } => "Container",
+            PawnData::Dice { .. } => "Dice",
+            PawnData::HiddenZone { .. } => "HiddenZone",
+            PawnData::Pawn {} => "Pawn",
+        }
+    }
+}
+
+// Used to narrow spatial queries from Lua, e.g. `{ name = "Queen", class = "DeckData" }`
+#[derive(Clone, Debug, Default)]
+pub struct PawnFilter {
+    pub name: Option<String>,
+    pub class: Option<String>,
+}
+impl PawnFilter {
+    pub fn matches(&self, pawn: &Pawn) -> bool {
+        self.name.as_ref().map_or(true, |name| pawn.name.as_ref() == Some(name)) &&
+        self.class.as_ref().map_or(true, |class| class.trim_end_matches("Data") == pawn.data.class())
+    }
+}
+impl<'lua> mlua::FromLua<'lua> for PawnFilter {
+    fn from_lua(value: mlua::Value<'lua>, _: &'lua mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Nil => Ok(Default::default()),
+            mlua::Value::Table(table) => Ok(PawnFilter {
+                name: table.get("name")?,
+                class: table.get("class")?,
+            }),
+            _ => Err(mlua::Error::FromLuaConversionError { from: "value", to: "PawnFilter", message: None })
+        }
+    }
+}
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
 pub struct PawnId(pub u64);
@@ -104,6 +187,13 @@ pub struct Pawn {
     pub texture: Option<String>,
 
     pub moveable: bool, // Physics properties
+    #[serde(default)]
+    pub physics: PhysicsProperties,
+
+    #[serde(skip_deserializing)]
+    pub owner: Option<UserId>, // Visibility, only set by the server
+    #[serde(skip_deserializing)]
+    pub visible_to: Option<Vec<UserId>>, // - None is visible to everyone
     
     pub position: Vec3, // Mutable Properties
     pub rotation: Quat,
@@ -118,11 +208,17 @@ pub struct Pawn {
 	pub rigid_body: Option<RigidBodyHandle>,
 	#[serde(skip, default = "Instant::now")]
     pub last_updated: Instant,
+    #[serde(skip)]
+    pub revealed_to: HashSet<UserId>, // Users who can see every face of this pawn
+    #[serde(skip)]
+    pub hidden_from: HashSet<UserId>, // Users this pawn has last been hidden from
 
     #[serde(skip)]
     pub on_grab_callback: Option<Arc<mlua::RegistryKey>>,
     #[serde(skip)]
     pub on_release_callback: Option<Arc<mlua::RegistryKey>>,
+    #[serde(skip)]
+    pub on_snap_callback: Option<Arc<mlua::RegistryKey>>,
 }
 impl PartialEq for Pawn {
     fn eq(&self, other: &Self) -> bool {
@@ -132,6 +228,9 @@ impl PartialEq for Pawn {
             self.tint == other.tint &&
             self.texture == other.texture &&
             self.moveable == other.moveable &&
+            self.physics == other.physics &&
+            self.owner == other.owner &&
+            self.visible_to == other.visible_to &&
             self.position == other.position &&
             self.rotation == other.rotation &&
             self.select_rotation == other.select_rotation &&
@@ -155,6 +254,10 @@ impl<'lua> mlua::FromLua<'lua> for Pawn {
                 tint: params.get("tint").ok(),
                 texture: params.get("texture").ok(),
                 moveable: params.get::<_, mlua::Value>("moveable").ok().and_then(|x| x.as_boolean()).unwrap_or(true),
+                physics: params.get::<_, Option<PhysicsProperties>>("physics")?.unwrap_or_default(),
+
+                owner: params.get::<_, Option<u64>>("owner")?.map(UserId),
+                visible_to: params.get::<_, Option<Vec<u64>>>("visible_to")?.map(|v| v.into_iter().map(UserId).collect()),
     
                 position: params.get("position").ok().unwrap_or_default(),
                 rotation: params.get("rotation").ok().unwrap_or_default(),
@@ -164,11 +267,15 @@ impl<'lua> mlua::FromLua<'lua> for Pawn {
                 data: params.get::<_, Option<PawnData>>("data")?.unwrap_or(PawnData::Pawn { }),
                 rigid_body: None,
                 last_updated: Instant::now(),
+                revealed_to: HashSet::new(),
+                hidden_from: HashSet::new(),
 
                 on_grab_callback: params.get::<_, mlua::Function>("on_grab")
                                         .ok().map(|cb| Arc::new(lua.create_registry_value(cb).unwrap())),
                 on_release_callback: params.get::<_, mlua::Function>("on_release")
-                                           .ok().map(|cb| Arc::new(lua.create_registry_value(cb).unwrap()))
+                                           .ok().map(|cb| Arc::new(lua.create_registry_value(cb).unwrap())),
+                on_snap_callback: params.get::<_, mlua::Function>("on_snap")
+                                        .ok().map(|cb| Arc::new(lua.create_registry_value(cb).unwrap()))
             })
         } else {
             Err(mlua::Error::FromLuaConversionError { from: "table", to: "Pawn", message: None })
@@ -185,11 +292,14 @@ impl<'lua> mlua::IntoLua<'lua> for Pawn {
         table.set("texture", self.texture)?;
 
         table.set("moveable", self.moveable)?;
+        table.set("physics", self.physics)?;
+        table.set("owner", self.owner.map(|id| id.0))?;
+        table.set("visible_to", self.visible_to.map(|v| v.into_iter().map(|id| id.0).collect::<Vec<_>>()))?;
         table.set("position", self.position)?;
         table.set("rotation", self.rotation)?;
         table.set("select_rotation", self.select_rotation)?;
 
-        //table.set("data", self.data)?;
+        table.set("data", self.data)?;
 
         let pawn_table = lua.globals().get::<_, mlua::Table>("Pawn")?;
         pawn_table.get::<_, mlua::Function>("new")?.call((pawn_table, table))
@@ -206,11 +316,15 @@ pub struct PawnUpdate {
     pub tint: Option<u64>,
 
     pub moveable: Option<bool>,
+    pub physics: Option<PhysicsProperties>,
+    pub owner: Option<Option<UserId>>,
+    pub visible_to: Option<Option<Vec<UserId>>>,
     
     pub position: Option<Vec3>,
     pub rotation: Option<Quat>,
     pub selected: Option<bool>,
     pub select_rotation: Option<Quat>,
+    pub shuffle: Option<bool>, // Sent by clients shuffling a deck, never relayed
     
     #[serde(flatten)]
     pub data: Option<PawnData>,
@@ -245,6 +359,9 @@ impl Pawn {
         p!(option: mesh);
         p!(option: tint);
         p!(moveable);
+        p!(physics);
+        p!(owner);
+        p!(visible_to);
         p!(position);
         p!(rotation);
         p!(select_rotation);
@@ -264,4 +381,102 @@ impl Pawn {
     pub fn flipped(&self) -> bool {
         (Rotation::from(&self.select_rotation).transform_vector(&Vector::new(0., 1., 0.))).y < 0.
     }
-}
\ No newline at end of file
+
+    // -- SNAPPING --
+
+    /// If this is a snap point accepting `pawn`, the nearest grid cell to the pawn's position
+    /// within range as `(cell, world position, distance)`. Height is ignored, the pawn keeps its own.
+    pub fn snap(&self, pawn: &Pawn) -> Option<((u64, u64), Vec3, f32)> {
+        let PawnData::SnapPoint { radius, size, scale, snaps } = &self.data else { return None; };
+        if !snaps.is_empty() && !pawn.name.as_ref().is_some_and(|name| snaps.contains(name)) { return None; }
+
+        let (radius, scale) = (*radius as f32, *scale as f32);
+        let half_extents = Vector::new(size.x as f32 - 1., 0., size.y as f32 - 1.)/2.;
+        let rotation = Rotation::from(&self.rotation);
+        let origin = Vector::from(&self.position);
+
+        // Transform position into grid space
+        let mut local = rotation.inverse_transform_vector(&(Vector::from(&pawn.position) - origin))/scale + half_extents;
+        local.y = 0.;
+        let cell = local.map(|x| x.round());
+
+        let distance = (local - cell).norm() * scale;
+        if distance >= radius || cell.x < 0. || cell.x >= size.x as f32 || cell.z < 0. || cell.z >= size.y as f32 {
+            return None;
+        }
+
+        // Transform grid cell back into world space
+        let mut position = Vec3::from(&(origin + rotation.transform_vector(&((cell - half_extents) * scale))));
+        position.y = pawn.position.y;
+        Some(((cell.x as u64, cell.z as u64), position, distance))
+    }
+    /// Snap `rotation` about this pawn's up axis to the nearest multiple of `increment`
+    pub fn snap_rotation(&self, rotation: &Quat, increment: f32) -> Quat {
+        let frame = Rotation::from(&self.rotation);
+        let relative = frame.inverse() * Rotation::from(rotation);
+
+        let angle = 2. * relative.j.atan2(relative.w);
+        let correction = Rotation::from_axis_angle(&Vector::y_axis(), (angle/increment).round() * increment - angle);
+        Quat::from(&(frame * correction * relative))
+    }
+
+    // -- VISIBILITY --
+
+    /// Whether `user` is allowed to know this pawn exists (ignoring hidden zones)
+    pub fn visible_to_user(&self, user: UserId) -> bool {
+        self.owner == Some(user) || self.visible_to.as_ref().map_or(true, |users| users.contains(&user))
+    }
+    /// Whether `position` lies within this pawn, if it's a hidden zone
+    pub fn zone_contains(&self, position: &Vec3) -> bool {
+        if let PawnData::HiddenZone { size } = &self.data {
+            let local = Rotation::from(&self.rotation).inverse_transform_vector(
+                &(Vector::from(position) - Vector::from(&self.position))
+            );
+            local.x.abs() <= size.x as f32/2. && local.y.abs() <= size.y as f32/2. && local.z.abs() <= size.z as f32/2.
+        } else {
+            false
+        }
+    }
+    /// Whether any of this pawn's faces can be hidden from users (only decks with a back can be face-down)
+    pub fn concealable(&self) -> bool {
+        matches!(&self.data, PawnData::Deck { back: Some(_), .. })
+    }
+    /// This pawn's data as seen by `user`. Faces the user isn't entitled to see are replaced with the deck's back,
+    /// which is what the client would have rendered for them anyway. Users can see:
+    ///  - The top card of a face-up deck
+    ///  - Every card of a pawn explicitly revealed to them
+    pub fn data_for(&self, user: UserId) -> Cow<'_, PawnData> {
+        if !self.concealable() || self.revealed_to.contains(&user) { return Cow::Borrowed(&self.data); }
+
+        let mut data = self.data.clone();
+        if let PawnData::Deck { contents, back: Some(back), .. } = &mut data {
+            let visible_top = !self.flipped();
+            for (i, card) in contents.iter_mut().enumerate() {
+                if !(visible_top && i == 0) {
+                    card.clone_from(back);
+                }
+            }
+        }
+        Cow::Owned(data)
+    }
+    pub fn redacted_for(&self, user: UserId) -> Cow<'_, Pawn> {
+        match self.data_for(user) {
+            Cow::Borrowed(_) => Cow::Borrowed(self),
+            Cow::Owned(data) => {
+                let mut pawn = self.clone();
+                pawn.data = data;
+                Cow::Owned(pawn)
+            }
+        }
+    }
+}
+// Pawns selected by one user and moved rigidly together
+pub struct PawnGroup {
+    pub pose: Isometry<f32>,
+    pub offsets: Vec<(PawnId, Isometry<f32>)>, // Relative to the group pose
+}
+impl PawnGroup {
+    pub fn ids(&self) -> Vec<PawnId> {
+        self.offsets.iter().map(|(id, _)| *id).collect()
+    }
+}
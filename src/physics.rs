@@ -2,8 +2,9 @@ use rapier3d::prelude::*;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::{UnboundedSender, UnboundedReceiver};
 use serde::{Serialize, Deserialize};
+use serde_with::skip_serializing_none;
 
-use crate::math::Vec3;
+use crate::math::{Quat, Vec2, Vec3};
 
 const PHYSICS_SCALE: f32 = 1.0/8.0;
 
@@ -13,6 +14,227 @@ pub struct CollisionAudioInfo {
     impulse: f32,
 }
 
+// Table bounds and forces, configurable per game
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Environment {
+    pub extents: Vec2, // Distance from the center to the walls along x and z
+    pub ground: f64,
+    pub ceiling: f64,
+    pub gravity: Vec3,
+    pub colliders: Vec<StaticBox>, // Extra immovable geometry, e.g. raised table edges
+}
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            extents: Vec2 { x: 80., y: 80. },
+            ground: 0.,
+            ceiling: 500.,
+            gravity: Vec3 { x: 0., y: -9.8, z: 0. },
+            colliders: vec![],
+        }
+    }
+}
+impl Environment {
+    /// Err if the table would have no room, e.g. walls on top of each other or the ceiling below the ground
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = |v: f64| v.is_finite() && v > 0.;
+        if !positive(self.extents.x) || !positive(self.extents.y) {
+            return Err(format!("Environment extents must be positive, got {:?}", self.extents));
+        }
+        if !self.ground.is_finite() || !self.ceiling.is_finite() || self.ceiling <= self.ground {
+            return Err(format!("Environment ceiling ({}) must be above the ground ({})", self.ceiling, self.ground));
+        }
+        Ok(())
+    }
+}
+impl<'lua> mlua::FromLua<'lua> for Environment {
+    fn from_lua(value: mlua::Value<'lua>, _: &'lua mlua::Lua) -> mlua::Result<Self> {
+        if let Some(table) = value.as_table() {
+            // Unset values keep their defaults
+            let default = Environment::default();
+            let environment = Environment {
+                extents: table.get::<_, Option<Vec2>>("extents")?.unwrap_or(default.extents),
+                ground: table.get::<_, Option<f64>>("ground")?.unwrap_or(default.ground),
+                ceiling: table.get::<_, Option<f64>>("ceiling")?.unwrap_or(default.ceiling),
+                gravity: table.get::<_, Option<Vec3>>("gravity")?.unwrap_or(default.gravity),
+                colliders: table.get::<_, Option<Vec<mlua::Table>>>("colliders")?.unwrap_or_default()
+                    .into_iter().map(|collider| Ok(StaticBox {
+                        position: collider.get("position")?,
+                        rotation: collider.get::<_, Option<Quat>>("rotation")?.unwrap_or(Quat::identity()),
+                        size: collider.get("size")?,
+                    })).collect::<mlua::Result<Vec<StaticBox>>>()?,
+            };
+            environment.validate().map_err(mlua::Error::RuntimeError)?;
+            Ok(environment)
+        } else {
+            Err(mlua::Error::FromLuaConversionError { from: "value", to: "Environment", message: None })
+        }
+    }
+}
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct StaticBox {
+    pub position: Vec3,
+    #[serde(default = "Quat::identity")]
+    pub rotation: Quat,
+    pub size: Vec3,
+}
+
+// Collider generated for models that don't define any
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ColliderFallback {
+    None,
+    #[default]
+    Box, // Bounding box of every mesh
+    Hull, // Convex hull of every vertex
+}
+impl<'lua> mlua::FromLua<'lua> for ColliderFallback {
+    fn from_lua(value: mlua::Value<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
+        match String::from_lua(value, lua)?.as_str() {
+            "none" => Ok(ColliderFallback::None),
+            "box" => Ok(ColliderFallback::Box),
+            "hull" => Ok(ColliderFallback::Hull),
+            other => Err(mlua::Error::RuntimeError(format!("Unknown collider fallback `{}`", other))),
+        }
+    }
+}
+impl<'lua> mlua::IntoLua<'lua> for ColliderFallback {
+    fn into_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        match self {
+            ColliderFallback::None => "none",
+            ColliderFallback::Box => "box",
+            ColliderFallback::Hull => "hull",
+        }.into_lua(lua)
+    }
+}
+
+// Optional per-pawn physical properties, unset values fall back to the table defaults.
+// Named the same in Lua, JSON and GLTF extras, so properties can be copied between them
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct PhysicsProperties {
+    pub mass: Option<f32>, // Total mass, split between colliders. Takes priority over density
+    pub density: Option<f32>,
+    pub friction: Option<f32>,
+    pub restitution: Option<f32>,
+    pub linear_damping: Option<f32>,
+    pub angular_damping: Option<f32>,
+    pub ccd: Option<bool>,
+    pub gravity_scale: Option<f32>,
+    pub collider_fallback: Option<ColliderFallback>, // Overrides the game's setting
+}
+impl PhysicsProperties {
+    const DEFAULTS: PhysicsProperties = PhysicsProperties {
+        mass: None,
+        density: None,
+        friction: Some(0.7),
+        restitution: Some(0.0),
+        linear_damping: Some(1.0),
+        angular_damping: Some(0.5),
+        ccd: Some(true), // Enable CCD on everything by default
+        gravity_scale: Some(1.0),
+        collider_fallback: None,
+    };
+
+    /// Fill unset values from `other`
+    pub fn or(&self, other: &PhysicsProperties) -> PhysicsProperties {
+        PhysicsProperties {
+            mass: self.mass.or(other.mass),
+            density: self.density.or(other.density),
+            friction: self.friction.or(other.friction),
+            restitution: self.restitution.or(other.restitution),
+            linear_damping: self.linear_damping.or(other.linear_damping),
+            angular_damping: self.angular_damping.or(other.angular_damping),
+            ccd: self.ccd.or(other.ccd),
+            gravity_scale: self.gravity_scale.or(other.gravity_scale),
+            collider_fallback: self.collider_fallback.or(other.collider_fallback),
+        }
+    }
+    /// Drop values that would break the simulation (NaN, negative masses) and clamp the rest to sane ranges
+    fn clamped(&self) -> PhysicsProperties {
+        let clamp = |v: Option<f32>, min: f32, max: f32| v.filter(|v| v.is_finite()).map(|v| v.clamp(min, max));
+        PhysicsProperties {
+            mass: clamp(self.mass, 0.001, 1000.),
+            density: clamp(self.density, 0.001, 1000.),
+            friction: clamp(self.friction, 0., 10.),
+            restitution: clamp(self.restitution, 0., 1.),
+            linear_damping: clamp(self.linear_damping, 0., 100.),
+            angular_damping: clamp(self.angular_damping, 0., 100.),
+            ccd: self.ccd,
+            gravity_scale: clamp(self.gravity_scale, -10., 10.),
+            collider_fallback: self.collider_fallback,
+        }
+    }
+    fn resolved(&self) -> PhysicsProperties {
+        self.clamped().or(&Self::DEFAULTS)
+    }
+    pub fn rigid_body(&self, builder: RigidBodyBuilder) -> RigidBodyBuilder {
+        let properties = self.resolved();
+        builder
+            .linear_damping(properties.linear_damping.unwrap())
+            .angular_damping(properties.angular_damping.unwrap())
+            .ccd_enabled(properties.ccd.unwrap())
+            .gravity_scale(properties.gravity_scale.unwrap())
+    }
+    pub fn collider(&self, builder: ColliderBuilder, collider_count: usize) -> ColliderBuilder {
+        let properties = self.resolved();
+        let builder = builder
+            .friction(properties.friction.unwrap())
+            .restitution(properties.restitution.unwrap())
+            .active_events(ActiveEvents::COLLISION_EVENTS);
+        match (properties.mass, properties.density) {
+            (Some(mass), _) => builder.mass(mass / collider_count.max(1) as f32),
+            (None, Some(density)) => builder.density(density),
+            (None, None) => builder.mass(0.01),
+        }
+    }
+    /// Apply to an existing rigidbody, resetting unset values to their defaults.
+    /// Colliders are rebuilt instead, so they pick up their GLTF extras again.
+    pub fn apply(&self, rb: &mut RigidBody) {
+        let properties = self.resolved();
+        rb.set_linear_damping(properties.linear_damping.unwrap());
+        rb.set_angular_damping(properties.angular_damping.unwrap());
+        rb.enable_ccd(properties.ccd.unwrap());
+        rb.set_gravity_scale(properties.gravity_scale.unwrap(), true);
+    }
+}
+impl<'lua> mlua::FromLua<'lua> for PhysicsProperties {
+    fn from_lua(value: mlua::Value<'lua>, _: &'lua mlua::Lua) -> mlua::Result<Self> {
+        if let Some(table) = value.as_table() {
+            Ok(PhysicsProperties {
+                mass: table.get("mass")?,
+                density: table.get("density")?,
+                friction: table.get("friction")?,
+                restitution: table.get("restitution")?,
+                linear_damping: table.get("linear_damping")?,
+                angular_damping: table.get("angular_damping")?,
+                ccd: table.get("ccd")?,
+                gravity_scale: table.get("gravity_scale")?,
+                collider_fallback: table.get("collider_fallback")?,
+            })
+        } else {
+            Err(mlua::Error::FromLuaConversionError { from: "value", to: "PhysicsProperties", message: None })
+        }
+    }
+}
+impl<'lua> mlua::IntoLua<'lua> for PhysicsProperties {
+    fn into_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("mass", self.mass)?;
+        table.set("density", self.density)?;
+        table.set("friction", self.friction)?;
+        table.set("restitution", self.restitution)?;
+        table.set("linear_damping", self.linear_damping)?;
+        table.set("angular_damping", self.angular_damping)?;
+        table.set("ccd", self.ccd)?;
+        table.set("gravity_scale", self.gravity_scale)?;
+        table.set("collider_fallback", self.collider_fallback)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
 pub struct TokioEventCollector {
     event_sender: UnboundedSender<(CollisionEvent, Option<ContactPair>)>
 }
@@ -56,9 +278,13 @@ pub struct PhysicsWorld {
     pub impulse_joint_set: ImpulseJointSet,
     pub multibody_joint_set: MultibodyJointSet,
     pub ccd_solver: CCDSolver,
+    pub query_pipeline: QueryPipeline,
 
     pub event_handler: TokioEventCollector,
     pub event_receiver: UnboundedReceiver<(CollisionEvent, Option<ContactPair>)>,
+
+    pub environment: Environment,
+    environment_colliders: Vec<ColliderHandle>,
 }
 impl PhysicsWorld {
     pub fn new(dt: f32) -> PhysicsWorld {
@@ -90,34 +316,60 @@ impl PhysicsWorld {
             impulse_joint_set: ImpulseJointSet::new(),
             multibody_joint_set: MultibodyJointSet::new(),
             ccd_solver: CCDSolver::new(),
+            query_pipeline: QueryPipeline::new(),
 
             event_handler: TokioEventCollector::new(collision_tx),
             event_receiver: collision_rx,
+
+            environment: Default::default(),
+            environment_colliders: vec![],
         };
-		
-        // Ground
-		w.collider_set.insert(ColliderBuilder::halfspace(Vector::y_axis()).build());
-  
-        // Ceiling
-		w.collider_set.insert(ColliderBuilder::halfspace(-Vector::y_axis())
-                                    .translation(Vector::y_axis().into_inner() * 500.).build());
-
-        // Walls
-        let wall_distance = 80.;
-		w.collider_set.insert(ColliderBuilder::halfspace(Vector::x_axis())
-                                    .translation(Vector::x_axis().into_inner() * -wall_distance).build());
-		w.collider_set.insert(ColliderBuilder::halfspace(-Vector::x_axis())
-                                    .translation(Vector::x_axis().into_inner() * wall_distance).build());
-		w.collider_set.insert(ColliderBuilder::halfspace(Vector::z_axis())
-                                    .translation(Vector::z_axis().into_inner() * -wall_distance).build());
-		w.collider_set.insert(ColliderBuilder::halfspace(-Vector::z_axis())
-                                    .translation(Vector::z_axis().into_inner() * wall_distance).build());
+        w.configure(Environment::default());
 		
 		return w;
     }
+    /// Rebuild the table bounds and static geometry
+    pub fn configure(&mut self, environment: Environment) {
+        for handle in std::mem::take(&mut self.environment_colliders) {
+            self.remove_collider(handle);
+        }
+
+        let ground = environment.ground as f32;
+        let (wall_x, wall_z) = (environment.extents.x as f32, environment.extents.y as f32);
+        let mut colliders = vec![
+            // Ground
+            ColliderBuilder::halfspace(Vector::y_axis())
+                .translation(Vector::y_axis().into_inner() * ground).build(),
+            // Ceiling
+            ColliderBuilder::halfspace(-Vector::y_axis())
+                .translation(Vector::y_axis().into_inner() * environment.ceiling as f32).build(),
+            // Walls
+            ColliderBuilder::halfspace(Vector::x_axis())
+                .translation(Vector::x_axis().into_inner() * -wall_x).build(),
+            ColliderBuilder::halfspace(-Vector::x_axis())
+                .translation(Vector::x_axis().into_inner() * wall_x).build(),
+            ColliderBuilder::halfspace(Vector::z_axis())
+                .translation(Vector::z_axis().into_inner() * -wall_z).build(),
+            ColliderBuilder::halfspace(-Vector::z_axis())
+                .translation(Vector::z_axis().into_inner() * wall_z).build(),
+        ];
+        colliders.extend(environment.colliders.iter().map(|c| {
+            ColliderBuilder::cuboid((c.size.x/2.) as f32, (c.size.y/2.) as f32, (c.size.z/2.) as f32)
+                .translation(Vector::from(&c.position))
+                .rotation(Rotation::from(&c.rotation).scaled_axis())
+                .friction(0.7).build()
+        }));
+        self.environment_colliders = colliders.into_iter().map(|c| self.collider_set.insert(c)).collect();
+
+        // Sleeping bodies wouldn't notice the new gravity or floor
+        for (_, rb) in self.rigid_body_set.iter_mut() {
+            rb.wake_up(true);
+        }
+        self.environment = environment;
+    }
     pub fn step(&mut self) {
         self.physics_pipeline.step(
-            &vector![0.0, -9.8 / PHYSICS_SCALE, 0.0],
+            &(Vector::from(&self.environment.gravity) / PHYSICS_SCALE),
             &self.integration_parameters,
 
 			&mut self.island_manager,
@@ -131,7 +383,7 @@ impl PhysicsWorld {
 			&mut self.multibody_joint_set,
 			&mut self.ccd_solver,
 
-            None,
+            Some(&mut self.query_pipeline),
 			&(),
 			&self.event_handler,
         );
@@ -143,6 +395,10 @@ impl PhysicsWorld {
         }
         events.into_iter()
     }
+    pub fn update_query_pipeline(&mut self) {
+        // Colliders may have been added or moved since the last step
+        self.query_pipeline.update(&self.collider_set);
+    }
     pub fn remove_rigidbody(&mut self, handle: RigidBodyHandle) {
         self.rigid_body_set.remove(handle,
                                    &mut self.island_manager,
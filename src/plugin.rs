@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use zip::{ZipArchive, ZipWriter, write::FileOptions};
+
+use crate::harness::Harness;
+use crate::lobby::{Asset, GameInfo, Lobby, MAX_ASSETS, MAX_ASSETS_SIZE, MAX_ASSET_SIZE};
+
+/// A plugin's manifest and the files it uploads, keyed by asset path (`/main.lua`)
+pub struct Plugin {
+    pub info: GameInfo,
+    pub files: BTreeMap<String, Vec<u8>>,
+    pub skipped: Vec<String>, // Files of a type the plugin loader doesn't upload
+}
+impl Plugin {
+    pub fn from_folder(folder: impl AsRef<Path>) -> Result<Plugin, Box<dyn Error>> {
+        let folder = folder.as_ref();
+        let manifest = fs::read_to_string(folder.join("manifest.json"))
+            .map_err(|e| format!("Failed to read manifest.json: {}", e))?;
+        let info: GameInfo = serde_json::from_str(&manifest)
+            .map_err(|e| format!("Invalid manifest.json: {}", e))?;
+
+        let mut files = BTreeMap::new();
+        let mut skipped = vec![];
+        for path in files_in(folder)? {
+            let name = format!("/{}", path.strip_prefix(folder)?.to_string_lossy().replace('\\', "/"));
+            if mime_type(&name).is_some() {
+                files.insert(name, fs::read(&path)?);
+            } else {
+                skipped.push(name);
+            }
+        }
+        Ok(Plugin { info, files, skipped })
+    }
+    /// Read a packed plugin, like the ones `plugins/pack.py` and `BG3D plugin` write.
+    /// Entries are checked against the asset limits as they're inflated, so uploads can be read safely.
+    pub fn from_zip(reader: impl Read + Seek) -> Result<Plugin, Box<dyn Error>> {
+        let mut archive = ZipArchive::new(reader)?;
+
+        let mut files = BTreeMap::new();
+        let mut skipped = vec![];
+        let mut size = 0;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            if entry.is_dir() { continue; }
+
+            let name = format!("/{}", entry.name().replace('\\', "/"));
+            if mime_type(&name).is_some() {
+                if files.len() >= MAX_ASSETS { return Err(format!("Too many assets (limit {})", MAX_ASSETS).into()); }
+                if files.contains_key(&name) { return Err(format!("Duplicate asset {}", name).into()); }
+
+                // Don't trust the size in the header, stop inflating once past the limit
+                let mut data = vec![];
+                entry.by_ref().take(MAX_ASSET_SIZE as u64 + 1).read_to_end(&mut data)?;
+                if data.len() > MAX_ASSET_SIZE {
+                    return Err(format!("Asset {} is over {} KiB", name, MAX_ASSET_SIZE/1024).into());
+                }
+                check_asset(&name, None, &data)?;
+                size += data.len();
+                if size > MAX_ASSETS_SIZE {
+                    return Err(format!("Assets total over {} KiB", MAX_ASSETS_SIZE/1024).into());
+                }
+                files.insert(name, data);
+            } else {
+                skipped.push(name);
+            }
+        }
+
+        let manifest = files.get("/manifest.json").ok_or("Failed to read manifest.json: missing")?;
+        let info: GameInfo = serde_json::from_slice(manifest)
+            .map_err(|e| format!("Invalid manifest.json: {}", e))?;
+        Ok(Plugin { info, files, skipped })
+    }
+
+    pub fn size(&self) -> usize {
+        self.files.values().map(|data| data.len()).sum()
+    }
+    /// Assets as data URLs, for sending in a `register_game` event
+    pub fn data_urls(&self) -> HashMap<String, String> {
+        self.files.iter()
+            .map(|(name, data)| (name.clone(), data_url(mime_type(name).unwrap(), data)))
+            .collect()
+    }
+    /// Assets as a lobby stores them, for installing without going through data URLs
+    pub fn into_assets(self) -> HashMap<String, Asset> {
+        self.files.into_iter()
+            .map(|(name, data)| {
+                let mime_type = mime_type(&name).unwrap().to_string();
+                (name, Asset::new(mime_type, data))
+            })
+            .collect()
+    }
+
+    /// Everything that would stop this plugin from loading, checked without starting it
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        // Same limits as `Lobby::register_assets`
+        if self.files.len() > MAX_ASSETS {
+            problems.push(format!("Too many assets: {} (limit {})", self.files.len(), MAX_ASSETS));
+        }
+        if self.size() > MAX_ASSETS_SIZE {
+            problems.push(format!("Assets total {} KiB (limit {} KiB)", self.size()/1024, MAX_ASSETS_SIZE/1024));
+        }
+        for (name, data) in self.files.iter().filter(|(_, data)| data.len() > MAX_ASSET_SIZE) {
+            problems.push(format!("Asset {} is {} KiB (limit {} KiB)", name, data.len()/1024, MAX_ASSET_SIZE/1024));
+        }
+
+        for (name, data) in self.files.iter() {
+            if let Err(e) = check_asset(name, None, data) {
+                problems.push(e);
+            }
+        }
+
+        // The manifest must have parsed as a `GameInfo`, but empty or misspelled fields still slip through
+        for (field, value) in [("name", &self.info.name), ("description", &self.info.description), ("author", &self.info.author)] {
+            if value.trim().is_empty() {
+                problems.push(format!("manifest.json has an empty \"{}\"", field));
+            }
+        }
+        if let (Some(serde_json::Value::Object(manifest)), Ok(serde_json::Value::Object(known))) = (
+            self.files.get("/manifest.json").and_then(|data| serde_json::from_slice(data).ok()),
+            serde_json::to_value(&self.info),
+        ) {
+            for field in manifest.keys().filter(|field| !known.contains_key(*field)) {
+                problems.push(format!("manifest.json has unknown field \"{}\"", field));
+            }
+        }
+        if !self.files.contains_key("/main.lua") {
+            problems.push("Missing main.lua".to_string());
+        }
+        // Compile every script in the same sandbox a lobby runs them in
+        let mut lobby = Lobby::new();
+        for (name, data) in self.files.iter().filter(|(name, _)| name.ends_with(".lua")) {
+            let Ok(source) = std::str::from_utf8(data) else {
+                problems.push(format!("{} isn't valid UTF-8", name));
+                continue;
+            };
+            if let Err(e) = lobby.lua_scope(|lua, _scope, _| {
+                lua.load(source).set_name(name.as_str()).into_function().map(|_| ())
+            }) {
+                problems.push(e.to_string());
+            }
+            for module in required_modules(source) {
+                if !self.files.contains_key(&format!("/{}.lua", module)) && Lobby::builtin_lua_module(&module).is_none() {
+                    problems.push(format!("{} requires missing module \"{}\"", name, module));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Package the plugin into a zip the plugin loader accepts
+    pub fn write_zip<W: Write + Seek>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut zip = ZipWriter::new(writer);
+        for (name, data) in self.files.iter() {
+            zip.start_file(name.trim_start_matches('/'), FileOptions::default())?;
+            zip.write_all(data)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+// Types a plugin can register as assets, others are skipped
+pub fn mime_type(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    Some(match extension.as_str() {
+        "lua" => "text/x-lua",
+        "json" => "application/json",
+        "gltf" => "model/gltf+json",
+        "glb" => "model/gltf-binary",
+        "bin" => "application/gltf-buffer",
+        "webp" => "image/webp",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        _ => return None,
+    })
+}
+/// The type `path` is served as, if it's an allowed type, any declared type agrees,
+/// and the contents look like it. Keeps hosts from serving e.g. HTML under the lobby's origin.
+pub fn check_asset(path: &str, declared: Option<&str>, data: &[u8]) -> Result<&'static str, String> {
+    let mime_type = mime_type(path).ok_or(format!("Asset {} isn't an allowed type", path))?;
+    if let Some(declared) = declared.filter(|declared| *declared != mime_type) {
+        return Err(format!("Asset {} declared as {} but should be {}", path, declared, mime_type));
+    }
+
+    let riff = |format: &[u8]| data.starts_with(b"RIFF") && data.get(8..12) == Some(format);
+    let valid = match mime_type {
+        "image/png" => data.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => data.starts_with(&[0xFF, 0xD8, 0xFF]),
+        "image/webp" => riff(b"WEBP"),
+        "model/gltf-binary" => data.starts_with(b"glTF"),
+        "audio/mpeg" => data.starts_with(b"ID3") || (data.len() > 1 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0),
+        "audio/ogg" => data.starts_with(b"OggS"),
+        "audio/wav" => riff(b"WAVE"),
+        "application/gltf-buffer" => true, // Raw vertex data, anything goes
+        text_type => match std::str::from_utf8(data) {
+            Err(_) => false,
+            Ok(text) => {
+                let text = text.trim_start_matches('\u{FEFF}').trim_start();
+                match text_type {
+                    "application/json" | "model/gltf+json" => serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok(),
+                    "image/svg+xml" => svg_is_inert(text),
+                    _ => !text.starts_with('<'), // Lua can't start with a tag
+                }
+            }
+        },
+    };
+    if !valid {
+        return Err(format!("Asset {} doesn't contain {}", path, mime_type));
+    }
+    Ok(mime_type)
+}
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+// Elements that only draw, so no scripts, links, animations (which can rewrite attributes) or foreign content
+const SVG_ELEMENTS: &[&str] = &[
+    "svg", "g", "defs", "symbol", "use", "title", "desc", "metadata",
+    "path", "rect", "circle", "ellipse", "line", "polyline", "polygon", "text", "tspan", "textPath", "image",
+    "linearGradient", "radialGradient", "stop", "pattern", "clipPath", "mask", "marker", "filter",
+];
+// Editor data Inkscape saves alongside the drawing, which browsers ignore
+const SVG_EDITOR_NAMESPACES: &[&str] = &[
+    "http://www.inkscape.org/namespaces/inkscape",
+    "http://sodipodi.sourceforge.net/DTD/sodipodi-0.dtd",
+];
+
+// SVGs are only drawn as images (e.g. deck borders), but are parsed and held to an allowlist
+// so that nothing in one could run if it were opened directly
+fn svg_is_inert(text: &str) -> bool {
+    let Ok(document) = roxmltree::Document::parse(text) else { return false; };
+    let root = document.root_element();
+    if root.tag_name().namespace() != Some(SVG_NAMESPACE) || root.tag_name().name() != "svg" {
+        return false;
+    }
+
+    root.descendants().filter(|node| node.is_element()).all(|node| {
+        let tag = node.tag_name();
+        let element_allowed = match tag.namespace() {
+            Some(SVG_NAMESPACE) => SVG_ELEMENTS.contains(&tag.name()) || tag.name().starts_with("fe"), // Filter primitives
+            Some(namespace) if SVG_EDITOR_NAMESPACES.contains(&namespace) => true,
+            Some("http://www.w3.org/1999/xhtml") => false,
+            // RDF and the like describing the drawing
+            _ => node.ancestors().any(|a| a.tag_name().namespace() == Some(SVG_NAMESPACE) && a.tag_name().name() == "metadata"),
+        };
+        element_allowed && node.attributes().all(|attribute| {
+            // Values come with entities decoded, browsers also ignore whitespace and case in URL schemes
+            let value: String = attribute.value().chars()
+                .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+                .collect::<String>().to_ascii_lowercase();
+            let name = attribute.name().to_ascii_lowercase();
+            let href_allowed = name != "href" || value.starts_with('#')
+                || ["data:image/png", "data:image/jpeg", "data:image/webp"].iter().any(|t| value.starts_with(t));
+            !name.starts_with("on") && href_allowed && !value.contains("javascript:")
+        })
+    })
+}
+fn data_url(mime_type: &str, data: &[u8]) -> String {
+    let mut url = format!("data:{},", mime_type);
+    for &byte in data {
+        if byte.is_ascii_alphanumeric() {
+            url.push(byte as char);
+        } else {
+            write!(url, "%{:02X}", byte).unwrap();
+        }
+    }
+    url
+}
+fn files_in(folder: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            paths.extend(files_in(&path)?);
+        } else {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+// Modules named by literal `require "name"` or `require("name")` calls
+fn required_modules(source: &str) -> Vec<String> {
+    source.match_indices("require").filter_map(|(i, _)| {
+        let rest = source[i + "require".len()..].trim_start();
+        let rest = rest.strip_prefix('(').unwrap_or(rest).trim_start();
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let name = rest[1..].split(quote).next()?;
+        Some(name.to_string())
+    }).collect()
+}
+
+/// `BG3D plugin <folder> [--out <zip>] [--start]`: check a plugin folder and package it,
+/// optionally starting it headlessly. Returns the process exit code.
+pub fn cli(args: &[String]) -> i32 {
+    let Some(folder) = args.first().map(PathBuf::from) else {
+        println!("Usage: BG3D plugin <folder> [--out <zip>] [--start]");
+        return 2;
+    };
+    let out = match args.iter().position(|a| a == "--out").and_then(|i| args.get(i + 1)) {
+        Some(out) => PathBuf::from(out),
+        // Next to the folder and named after it, so `BG3D plugin .` works too
+        None => match folder.canonicalize().ok().filter(|path| path.file_name().is_some()) {
+            Some(path) => path.with_file_name(format!("{}.zip", path.file_name().unwrap().to_string_lossy())),
+            None => {
+                println!("Can't name a zip after {:?}, pass --out <zip>", folder);
+                return 2;
+            }
+        },
+    };
+    let start = args.iter().any(|a| a == "--start");
+
+    println!("Checking plugin {:?}...", folder);
+    let plugin = match Plugin::from_folder(&folder) {
+        Ok(plugin) => plugin,
+        Err(e) => {
+            println!(" - {}", e);
+            return 1;
+        }
+    };
+    println!(" - \"{}\" by {}, {} assets, {} KiB", plugin.info.name, plugin.info.author, plugin.files.len(), plugin.size()/1024);
+    for name in &plugin.skipped {
+        println!(" - Skipping {} (unsupported type)", name);
+    }
+
+    let problems = plugin.problems();
+    for problem in &problems {
+        println!(" - {}", problem);
+    }
+    if !problems.is_empty() {
+        return 1;
+    }
+
+    if start {
+        match start_headless(&plugin) {
+            Ok(errors) if errors.is_empty() => {},
+            Ok(errors) => {
+                errors.iter().for_each(|e| println!(" - {}", e));
+                return 1;
+            },
+            Err(e) => {
+                println!(" - Failed to start: {}", e);
+                return 1;
+            }
+        }
+    }
+
+    if let Err(e) = fs::File::create(&out).map_err(|e| e.into()).and_then(|file| plugin.write_zip(file)) {
+        println!("Failed to write {:?}: {}", out, e);
+        return 1;
+    }
+    println!("Wrote {:?}", out);
+    0
+}
+// Run `game.start` and a few seconds of physics, listing spawned pawns and returning any errors
+fn start_headless(plugin: &Plugin) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut harness = Harness::new();
+    let host = harness.connect()?;
+    harness.register_plugin(host, plugin)?;
+    harness.step(90)?;
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for pawn in harness.pawns() {
+        *counts.entry(pawn.name.clone().unwrap_or(format!("unnamed {}", pawn.data.class()))).or_default() += 1;
+    }
+    println!("Started with {} pawns:", harness.pawns().count());
+    for (name, count) in counts {
+        println!(" - {}x {}", count, name);
+    }
+
+    Ok(harness.chat(host).into_iter().filter(|c| c.contains("error") || c.contains("Failed")).collect())
+}
@@ -0,0 +1,597 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+use std::error::Error;
+
+use axum::extract::RawQuery;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{
+    body::{Body, Bytes},
+    extract::{
+        Path as AxumPath,
+        ws::{Message, WebSocket, WebSocketUpgrade}
+    },
+    response::Redirect,
+    routing::{get, post},
+    Json,
+    Router,
+    http::{Uri, header::HeaderMap, header, Request}
+};
+use tower_http::{services::{ServeDir, ServeFile}, compression::CompressionLayer};
+use tower::ServiceExt;
+
+use futures_util::{StreamExt, SinkExt, TryFutureExt};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout, Duration, Instant, MissedTickBehavior};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use flate2::{Decompress, read::ZlibDecoder};
+
+use rapier3d::prelude::*;
+
+use crate::lobby::*;
+use crate::pawn::*;
+use crate::user::*;
+use crate::events::*;
+use crate::library::PluginLibrary;
+use crate::plugin::Plugin;
+use crate::PHYSICS_RATE;
+
+const MAX_CATCH_UP_STEPS: u32 = 5; // Beyond this an overloaded lobby runs slow instead of spiralling
+const CURSOR_RATE: f32 = 1.0/10.0;
+const ASSET_CHUNK_SIZE: usize = 64 * 1024;
+pub const MAX_UPLOAD_SIZE: usize = MAX_ASSETS_SIZE + 1024 * 1024; // Room for zip headers
+
+//TODO: Replace this with Dashmap?
+pub type Lobbies = Arc<RwLock<HashMap<String, Arc<Mutex<Lobby>>>>>;
+
+/// Extension points for services embedding the server. Every method has a permissive default.
+pub trait ServerHooks: Send + Sync {
+    /// Whether a websocket connection to `lobby` may be opened, e.g. by checking a session cookie
+    fn authorize(&self, _lobby: &str, _headers: &HeaderMap) -> bool { true }
+    /// A lobby was created for its first user, e.g. to restore saved settings or pawns
+    fn lobby_created(&self, _lobby: &mut Lobby) {}
+    /// The last user left and `lobby` is about to be dropped, e.g. to save it
+    fn lobby_removed(&self, _lobby: &Lobby) {}
+}
+pub struct DefaultHooks;
+impl ServerHooks for DefaultHooks {}
+
+/// A BG3D server: its lobbies plus the routes serving them.
+/// Static files are served from `static/` and `plugins/` relative to the working directory,
+/// and the zips and plugin folders in `plugins/` make up the library hosts can start games from.
+#[derive(Clone)]
+pub struct Server {
+    pub lobbies: Lobbies,
+    pub library: Arc<PluginLibrary>,
+    hooks: Arc<dyn ServerHooks>,
+}
+impl Default for Server {
+    fn default() -> Self {
+        Self::new(DefaultHooks)
+    }
+}
+impl Server {
+    pub fn new(hooks: impl ServerHooks + 'static) -> Server {
+        Server {
+            lobbies: Lobbies::default(),
+            library: Arc::new(PluginLibrary::scan("plugins")),
+            hooks: Arc::new(hooks)
+        }
+    }
+
+    /// Every route, including the front page and static files, to serve at the root of a domain
+    pub fn router(&self) -> Router {
+        let lobbies_dashboard_clone = self.lobbies.clone();
+        let library_catalog_clone = self.library.clone();
+
+        let index_routes = Router::new()
+            .route_service("/", ServeFile::new("static/frontpage/index.html"))
+            .route("/index.html", get(|| async { Redirect::to("/") }));
+
+        index_routes
+            .route("/dashboard", get(move || {
+                let lobbies = lobbies_dashboard_clone.clone();
+                dashboard(lobbies)
+            }))
+            .route("/catalog", get(move || {
+                let library = library_catalog_clone.clone();
+                async move { ([(header::CACHE_CONTROL, "no-cache")], Json(library.catalog())) }
+            }))
+            .nest_service("/static",
+                          ServeDir::new("static").append_index_html_on_directories(false))
+            .nest_service("/plugins",
+                          ServeDir::new("plugins").append_index_html_on_directories(false))
+            .nest("/:lobby", self.lobby_router())
+            .layer(CompressionLayer::new())
+    }
+    /// Routes for a single lobby, to be nested under a path with a `:lobby` parameter
+    pub fn lobby_router(&self) -> Router {
+        let lobbies_index_clone = self.lobbies.clone();
+        let lobbies_assets_clone = self.lobbies.clone();
+        let server_ws_clone = self.clone();
+        let server_upload_clone = self.clone();
+        let lobbies_page_clone = self.lobbies.clone();
+        let lobbies_page_path_clone = self.lobbies.clone();
+
+        Router::new()
+            .route("/", get(|AxumPath(lobby): AxumPath<String>, request: Request<Body>| async move {
+                let lobbies = lobbies_index_clone.clone();
+                if let Some(lobby) = lobbies.read().await.get(&lobby) {
+                    if lobby.lock().await.users.len() >= 32 {
+                        return (
+                            [(header::CACHE_CONTROL, "no-cache")],
+                            ServeFile::new("static/full.html").oneshot(request).await
+                        );
+                    }
+                }
+                return (
+                    [(header::CACHE_CONTROL, "no-cache")],
+                    ServeFile::new("static/index.html").oneshot(request).await
+                );
+            }))
+            .nest_service("/assets", ServeDir::new("static/games").fallback(get(
+                move |AxumPath(lobby): AxumPath<String>, uri: Uri, headers: HeaderMap| {
+                    let lobbies = lobbies_assets_clone.clone();
+                    println!("Someone requested asset path \"{}\" for lobby [{lobby}]", uri.path());
+
+                    retrieve_asset(lobbies, lobby, uri, headers)
+                }
+            )))
+            .route("/page/", get(
+                move |AxumPath(lobby): AxumPath<String>, RawQuery(query): RawQuery| {
+                    let lobbies = lobbies_page_clone.clone();
+                    let query = query.map(|q| format!("?{q}")).unwrap_or("".to_string());
+                    serve_page(lobbies, lobby, format!("/{query}"))
+                }
+            ))
+            .route("/page/*path", get(
+                move |AxumPath((lobby, path)): AxumPath<(String, String)>, RawQuery(query): RawQuery| {
+                    let lobbies = lobbies_page_path_clone.clone();
+                    let query = query.map(|q| format!("?{q}")).unwrap_or("".to_string());
+                    serve_page(lobbies, lobby, format!("/{path}{query}"))
+                }
+            ))
+            .route("/plugin", post(
+                |AxumPath(lobby): AxumPath<String>, headers: HeaderMap, body: Body| async move {
+                    let server = server_upload_clone.clone();
+                    upload_plugin(&server, lobby, headers, body).await
+                }
+            ))
+            .route("/ws", get(
+                |AxumPath(lobby): AxumPath<String>, ws: WebSocketUpgrade, headers: HeaderMap| async move {
+                    let server = server_ws_clone.clone();
+                    if !server.hooks.authorize(&lobby, &headers) {
+                        return StatusCode::FORBIDDEN.into_response();
+                    }
+                    ws.on_upgrade(move |socket| async move {
+                        if let Err(err) = user_connected(socket, lobby, &server, headers).await {
+                            println!("Error encountered in websocket connection: {:?}", err);
+                        }
+                    })
+                }
+            ))
+    }
+
+    /// Relay user statuses (cursors, head, etc) to every lobby, this must be running for a router to be usable
+    pub fn spawn_status_relay(&self) -> JoinHandle<()> {
+        let lobbies_clone = self.lobbies.clone();
+        tokio::task::spawn(async move {
+            let mut interval = interval(Duration::from_secs_f32(CURSOR_RATE));
+            loop {
+                {
+                    let lobbies_rl = lobbies_clone.read().await;
+                    for lobby in lobbies_rl.values() {
+                        let lobby = lobby.lock().await;
+                        lobby.relay_user_statuses().ok();
+                    }
+                }
+                interval.tick().await;
+            }
+        })
+    }
+}
+
+async fn dashboard(lobbies: Lobbies) -> String {
+    let lobbies = lobbies.read().await;
+
+    let mut lobbies_text = String::new();
+    for (name, lobby) in lobbies.iter() {
+        let lobby = lobby.lock().await;
+        lobbies_text += &format!(" - '{}' [{} user(s)]\n", name, lobby.users.len());
+    }
+
+    let (asset_count, asset_size) = Asset::stored();
+    format!(
+        include_str!("../static/dashboard.html"),
+        lobby_count = lobbies.len(),
+        asset_count = asset_count,
+        asset_kib = asset_size/1024,
+        lobbies = lobbies_text
+    )
+}
+async fn retrieve_asset(lobbies: Lobbies, lobby: String, path: Uri, headers: HeaderMap) -> axum::response::Result<impl IntoResponse> {
+    // Only hold the lobby long enough to take a reference to the contents
+    let (mime_type, data, version) = {
+        let lobbies_rl = lobbies.read().await;
+        let lobby = lobbies_rl.get(&lobby).ok_or(StatusCode::NOT_FOUND)?.lock().await;
+        let asset = lobby.assets.get(path.path()).ok_or(StatusCode::NOT_FOUND)?;
+        (asset.mime_type.clone(), asset.data.clone(), asset.version())
+    };
+
+    // URLs carrying the current version never change, others (e.g. GLTF buffers) revalidate
+    let etag = format!("\"{}\"", version);
+    let cache_control = if path.query() == Some(&format!("v={}", version)) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    if headers.get(header::IF_NONE_MATCH).is_some_and(|tag| tag.as_bytes() == etag.as_bytes()) {
+        return axum::response::Result::Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control.to_string())],
+        ).into_response());
+    }
+
+    // Stream the shared contents a chunk at a time, rather than copying the whole asset for every request
+    let length = data.len();
+    let chunks = futures_util::stream::iter((0..length).step_by(ASSET_CHUNK_SIZE).map(move |start| {
+        Ok::<_, Infallible>(Bytes::copy_from_slice(&data[start..length.min(start + ASSET_CHUNK_SIZE)]))
+    }));
+
+    // Uploads are checked, but still never let them run as a page
+    axum::response::Result::Ok((
+        [
+            (header::CONTENT_TYPE, mime_type),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, cache_control.to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CONTENT_SECURITY_POLICY, "default-src 'none'; style-src 'unsafe-inline'; sandbox".to_string())
+        ],
+        Body::from_stream(chunks)
+    ).into_response())
+}
+// Install a plugin zip POSTed by the host, authenticated with the token from their `start` event
+async fn upload_plugin(server: &Server, lobby_name: String, headers: HeaderMap, body: Body) -> Result<StatusCode, (StatusCode, String)> {
+    if !server.hooks.authorize(&lobby_name, &headers) {
+        return Err((StatusCode::FORBIDDEN, "Not authorized".to_string()));
+    }
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "Missing token".to_string()))?;
+
+    let lobby = server.lobbies.read().await.get(&lobby_name).cloned()
+        .ok_or((StatusCode::NOT_FOUND, "Lobby not found".to_string()))?;
+    let user_id = lobby.lock().await.host_with_token(token)
+        .ok_or((StatusCode::FORBIDDEN, "Only the host can load plugins".to_string()))?;
+
+    // Receive without holding the lobby, giving up as soon as the upload is too large
+    let mut data = Vec::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        if data.len() + chunk.len() > MAX_UPLOAD_SIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Plugins are limited to {} MiB", MAX_UPLOAD_SIZE/1024/1024)));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    let plugin = tokio::task::spawn_blocking(move || {
+        Plugin::from_zip(Cursor::new(data)).map_err(|e| e.to_string())
+    }).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+      .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let mut lobby = lobby.lock().await;
+    lobby.install_plugin(user_id, plugin).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    lobby.wake.notify_one();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Start a library plugin, reading it from disk without holding the lobby like `upload_plugin`
+async fn register_plugin(library: Arc<PluginLibrary>, lobby: &Mutex<Lobby>, user_id: UserId, id: String) -> Result<(), Box<dyn Error>> {
+    if lobby.lock().await.host != user_id { return Err("Failed to register plugin".into()); }
+
+    let plugin = tokio::task::spawn_blocking(move || library.load(&id).map_err(|e| e.to_string())).await??;
+    lobby.lock().await.install_plugin(user_id, plugin)
+}
+async fn serve_page(lobbies: Lobbies, lobby: String, path: String) -> axum::response::Result<impl IntoResponse> {
+    let lobbies_rl = lobbies.read().await;
+
+    let mut lobby = lobbies_rl.get(&lobby).ok_or(StatusCode::NOT_FOUND)?.lock().await;
+
+    let content: mlua::Result<Result<String, StatusCode>> = lobby.lua_scope(|lua, _scope, _| { // Call physics callback
+        if let Some(res) = Lobby::run_lua_callback::<_, String>(lua, "page", path) {
+            Ok(Ok(res?))
+        } else {
+            //Ok(Err(StatusCode::NOT_FOUND))
+            Ok(Ok("This plugin has no settings".to_string()))
+        }
+    });
+    // The page may have changed the table, e.g. spawned pawns or started timers
+    lobby.wake.notify_one();
+
+    let content = match content {
+        Err(e) => {
+            let _ = lobby.system_chat(Cow::Owned(format!("Lua error in game.page: `{}`", e)));
+            Err(StatusCode::NOT_FOUND)
+        },
+        Ok(r) => r
+    };
+
+    axum::response::Result::Ok((
+        [
+            ("Content-Type", "text/html"),
+            ("Cache-Control", "no-cache, no-store, must-revalidate"),
+            ("Access-Control-Allow-Origin", "null")
+        ],
+        content
+    ))
+}
+
+async fn user_connected(ws: WebSocket, lobby_name: String, server: &Server, headers: HeaderMap) -> Result<(), Box<dyn Error>> {
+    let lobbies = &server.lobbies;
+    let (mut tx, mut rx) = ws.split();
+    
+    let (buffer_tx, buffer_rx) = mpsc::unbounded_channel::<Message>();
+    
+    // Automatically send buffered messages
+    let mut buffer_rx = UnboundedReceiverStream::new(buffer_rx);
+    let buffer_task_handle = tokio::task::spawn(async move {
+        while let Some(message) = buffer_rx.next().await {
+            tx.send(message).unwrap_or_else(|_| {}).await
+        }
+    });
+
+    // Send keep-alive pings every 5 seconds
+    let cloned_tx = buffer_tx.clone();
+    let keep_alive_task_handle = tokio::task::spawn(async move {
+        let mut interval = interval(Duration::from_secs(5));
+        loop {
+            cloned_tx.send(Message::Ping(vec![])).ok();
+            interval.tick().await;
+        }
+    });
+    
+    // Track user
+    let (user_id, wake) = {
+        let mut host: bool = false;
+
+        // Create lobby if it doesn't exist
+        if lobbies.read().await.get(&lobby_name).is_none() {
+            let mut lobbies_wl = lobbies.write().await;
+
+            let mut lobby = Lobby::new();
+            lobby.name = lobby_name.clone();
+            lobby.library = server.library.clone();
+            server.hooks.lobby_created(&mut lobby);
+
+            let lobby_arc = Arc::new(Mutex::new(lobby));
+
+            // Step physics on the blocking pool so it can't starve the runtime, sleeping while the lobby is idle
+            let lobby_physics_clone = lobby_arc.clone();
+            tokio::task::spawn(async move {
+                let physics_rate_duration = Duration::from_secs_f32(PHYSICS_RATE);
+                let mut interval = interval(physics_rate_duration);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+                // Real time not yet simulated, stepped off in fixed increments
+                let mut accumulator = Duration::ZERO;
+                let mut last_time = Instant::now();
+                loop {
+                    interval.tick().await;
+                    let now = Instant::now();
+                    accumulator += now - last_time;
+                    last_time = now;
+
+                    let lobby_wl = lobby_physics_clone.clone().lock_owned().await;
+                    let stepped = tokio::task::spawn_blocking(move || {
+                        let mut lobby_wl = lobby_wl;
+                        if let Some(true) = lobby_wl.abort_token {
+                            return None;
+                        }
+                        let mut steps = 0;
+                        while accumulator >= physics_rate_duration && steps < MAX_CATCH_UP_STEPS {
+                            lobby_wl.step().ok();
+                            accumulator -= physics_rate_duration;
+                            steps += 1;
+                        }
+                        if accumulator >= physics_rate_duration {
+                            accumulator = Duration::ZERO; // Drop the backlog rather than fall further behind
+                        }
+
+                        Some((accumulator, lobby_wl.is_idle().then(|| lobby_wl.wake.clone())))
+                    }).await;
+                    let Ok(Some((remaining, idle))) = stepped else { return; };
+                    accumulator = remaining;
+
+                    let Some(wake) = idle else { continue; };
+                    wake.notified().await;
+
+                    // Time spent idle isn't owed to the simulation
+                    interval.reset();
+                    accumulator = Duration::ZERO;
+                    last_time = Instant::now();
+                }
+            });
+            lobby_arc.lock().await.abort_token = Some(false);
+
+            lobbies_wl.insert(lobby_name.clone(), lobby_arc);
+            host = true;
+        }
+
+        let lobbies_rl = lobbies.read().await;
+        let mut lobby = lobbies_rl.get(&lobby_name).ok_or("Lobby missing")?.lock().await;
+        let user_id = lobby.add_user(buffer_tx);
+
+        if host { lobby.host = user_id; }
+
+        (user_id, lobby.wake.clone())
+    };
+    
+    // Continually process received messages
+    // - Timeout at 10 seconds
+    loop {
+        let result = timeout(Duration::from_secs(10), rx.next()).await;
+        let message: Message = match result {
+            Ok(Some(r)) => match r {
+                Ok(m) => m,
+                Err(e) => {
+                    println!("Websocket connection error, user <{user_id:?}> disconnected: {}", e);
+                    break;
+                }
+            },
+            Ok(None) => {continue;},
+            Err(_) => {
+                println!("Websocket connection closed, user <{user_id:?}> timed-out");
+                break;
+            },
+        };
+        if matches!(message, Message::Close(_)) {
+            println!("Websocket connection closed, user <{user_id:?}> left");
+            break;
+        }
+        if !matches!(message, Message::Binary(_)) {
+            if matches!(message, Message::Pong(_)) { continue; } else {
+                println!("Received non-binary/non-pong message");
+                continue;
+            }
+        }
+
+        let lobbies_rl = lobbies.read().await;
+        let lobby = lobbies_rl.get(&lobby_name).ok_or("Lobby missing")?;
+
+        let message_bytes = message.into_data();
+        let mut d = Decompress::new(false);
+        d.set_dictionary(include_bytes!("dictionary.txt")).expect("Failed to set DEFLATE dictionary");
+        let mut deflate_decompressor = ZlibDecoder::new_with_decompress(message_bytes.as_slice(), d);
+        let mut message_text = String::new();
+        deflate_decompressor.read_to_string(&mut message_text).unwrap();
+
+        match serde_json::from_str(&message_text) {
+            Ok(event_data) => {
+                // Anything but cursors and pings may disturb the table, so resume stepping
+                let wakes_physics = !matches!(event_data, Event::UpdateUserStatuses { .. } | Event::Ping { .. });
+                let event_result = match event_data {
+                    Event::RegisterPlugin { id } => register_plugin(server.library.clone(), lobby, user_id, id.to_string()).await,
+                    event_data => lobby.lock().await.handle_event(user_id, event_data, &headers),
+                };
+
+                if let Err(err) = event_result {
+                    println!("Error encountered while handling event:");
+                    println!(" - Message: {:?}", message_text);
+                    println!(" - Error: {:?}", err);
+                }
+                if wakes_physics { wake.notify_one(); }
+            },
+            Err(err) => {
+                println!("User <{user_id:?}> sent malformed message: {:?}", err);
+                println!(" - Message: {:?}", message_text);
+            }
+        };
+    }
+
+    buffer_task_handle.abort();
+    keep_alive_task_handle.abort();
+    user_disconnected(user_id, &lobby_name, server).await
+}
+
+
+// --- USER EVENTS ---
+
+async fn user_disconnected(user_id: UserId, lobby_name: &str, server: &Server) -> Result<(), Box<dyn Error>> {
+    let lobbies = &server.lobbies;
+    let lobbies_rl = lobbies.read().await;
+    let mut lobby = lobbies_rl.get(lobby_name).ok_or("Missing lobby")?.lock().await;
+    
+    // Tell all other users that this user has disconnected
+    lobby.users.values()
+        .filter(|u| u.id != user_id)
+        .send_event(&Event::Disconnect {
+            id: user_id,
+        })?;
+
+    // Drop their group as if they'd let go, so its pawns' `on_release` callbacks run
+    if let Err(e) = lobby.release_group(user_id) {
+        println!("Error releasing group of user <{user_id:?}>: {:?}", e);
+    }
+
+    let lobby_mut_ref: &mut Lobby = &mut *lobby;
+    
+    // Remove user from lobby
+    let color_idx = lobby_mut_ref.users[&user_id].color_idx;
+    lobby_mut_ref.color_allocations[color_idx] = lobby_mut_ref.color_allocations[color_idx].saturating_sub(1);
+    lobby_mut_ref.users.remove(&user_id);
+
+    // Deselect all pawns selected by this user
+    let mut deselected_pawns: Vec<PawnUpdate> = Vec::new();
+    for pawn in lobby_mut_ref.pawns.values_mut() {
+        if pawn.selected_user == Some(user_id) {
+            pawn.selected_user = None;
+
+            if pawn.moveable {
+                let rb_handle = pawn.rigid_body.ok_or("Pawn missing rigidbody")?;
+                let rb = lobby_mut_ref.world.rigid_body_set.get_mut(rb_handle).ok_or("Invalid rigidbody handle")?;
+                rb.set_body_type(RigidBodyType::Dynamic, true);
+                for collider_handle in rb.colliders().iter() {
+                    let collider = lobby_mut_ref.world.collider_set.get_mut(*collider_handle).ok_or("Invalid collider handle")?;
+                    collider.set_sensor(false);
+                }
+            }
+
+            deselected_pawns.push(PawnUpdate {
+                id: pawn.id,
+                selected: Some(false),
+                ..Default::default()
+            });
+        }
+    }
+    // Released pawns fall, and a removed lobby's physics task has to see its abort token
+    lobby_mut_ref.wake.notify_one();
+    // Relay to other users that these pawns were deselected
+    lobby.users.values().filter(|u| u.id != user_id).send_event(
+        &Event::UpdatePawns { updates: deselected_pawns, collisions: None, tick: Some(lobby.tick) }
+    )?;
+
+    if let Err(e) = lobby.lua_scope(|lua, _scope, _| { // Call leave callback
+        if let Some(res) = Lobby::run_lua_callback(lua, "leave", user_id.0) {
+            res?;
+        }
+        Ok(())
+    }) {
+        lobby.system_chat(Cow::Owned(format!("Lua error in game.leave: `{}`", e)))?;
+    }
+    
+    if lobby.users.len() != 0 { // If the user id is the host, let's reassign the host to the next user
+        if lobby.host == user_id {
+            // Reassign host
+            let sorted_users = {
+                let mut v = lobby.users.keys().cloned().collect::<Vec<UserId>>();
+                v.sort();
+                v
+            };
+            lobby.host = *sorted_users.first().unwrap();
+
+            // Tell the new host
+            lobby.users.get(&lobby.host).unwrap().send_event(&Event::AssignHost { id: lobby.host })?;
+
+            println!("Host of lobby [{lobby_name}] left, reassigning <{user_id:?}> -> <{:?}>", lobby.host);
+        }
+    } else { // Otherwise, delete lobby if last user
+        //lobby.physics_handle.as_ref().ok_or("Attempting to remove lobby without physics handle")?.abort();
+        lobby.abort_token = Some(true);
+        server.hooks.lobby_removed(&lobby);
+        drop(lobby);
+        drop(lobbies_rl);
+
+        let mut lobbies_wl = lobbies.write().await;
+        lobbies_wl.remove(lobby_name);
+
+        println!("Lobby [{lobby_name}] removed");
+    }
+    Ok(())
+}
\ No newline at end of file
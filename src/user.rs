@@ -1,7 +1,7 @@
-use std::collections::HashMap;
 use std::error::Error;
 use std::io::{self, Write};
 use tokio::sync::{mpsc, mpsc::error::SendError};
+use indexmap::IndexMap;
 use serde::{Serialize, Deserialize};
 use axum::extract::ws::Message;
 use random_color::{Color, Luminosity, RandomColor, color_dictionary::ColorDictionary};
@@ -73,9 +73,11 @@ pub struct User {
 
     #[serde(skip)]
     pub color_idx: usize,
+    #[serde(skip)]
+    pub token: String, // Only sent to this user, authenticates their HTTP requests (e.g. plugin uploads)
 
     #[serde(skip)]
-    pub hand: HashMap<PawnId, Pawn>,
+    pub hand: IndexMap<PawnId, Pawn>, // In the order shown to the user
     #[serde(skip)]
     pub tx: mpsc::UnboundedSender<Message>,
 
@@ -92,9 +94,10 @@ impl User {
         User {
             id,
             tx,
-            hand: HashMap::new(),
+            hand: IndexMap::new(),
             color: RandomColor::new().dictionary(ColorDictionary::new()).hue(color).luminosity(Luminosity::Dark).to_hex(),
             color_idx,
+            token: format!("{:032x}", rand::random::<u128>()),
 
             cursor_position: Vec3 {x:0.0,y:0.0,z:0.0},
             head_position: Vec3 {x:0.0,y:0.0,z:0.0},
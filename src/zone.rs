@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+use rapier3d::prelude::*;
+
+use crate::math::*;
+use crate::pawn::PawnId;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ZoneId(pub u64);
+
+// Server-side region of the table, used by scripts for rule enforcement
+pub struct Zone {
+    pub id: ZoneId,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub size: Vec3,
+
+    pub collider: Option<ColliderHandle>,
+    pub contents: HashSet<PawnId>,
+
+    pub on_enter_callback: Option<Arc<mlua::RegistryKey>>,
+    pub on_exit_callback: Option<Arc<mlua::RegistryKey>>,
+}
+impl Zone {
+    pub fn collider(&self) -> Collider {
+        // Zones are fixed sensors, so also detect held (kinematic) pawns,
+        // but not other fixed pawns like boards
+        ColliderBuilder::cuboid((self.size.x/2.) as f32, (self.size.y/2.) as f32, (self.size.z/2.) as f32)
+            .translation(Vector::from(&self.position))
+            .rotation(Rotation::from(&self.rotation).scaled_axis())
+            .sensor(true)
+            .active_collision_types(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_FIXED)
+            .build()
+    }
+}
+
+impl<'lua> mlua::FromLua<'lua> for Zone {
+    fn from_lua(value: mlua::Value<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
+        if let mlua::Value::Table(params) = value {
+            Ok(Zone {
+                id: ZoneId(0),
+                position: params.get("position")?,
+                // Zones are axis-aligned unless given a rotation
+                rotation: params.get::<_, Option<Quat>>("rotation")?.unwrap_or(Quat::identity()),
+                size: params.get("size")?,
+
+                collider: None,
+                contents: HashSet::new(),
+
+                on_enter_callback: params.get::<_, mlua::Function>("on_enter")
+                                         .ok().map(|cb| Arc::new(lua.create_registry_value(cb).unwrap())),
+                on_exit_callback: params.get::<_, mlua::Function>("on_exit")
+                                        .ok().map(|cb| Arc::new(lua.create_registry_value(cb).unwrap())),
+            })
+        } else {
+            Err(mlua::Error::FromLuaConversionError { from: "table", to: "Zone", message: None })
+        }
+    }
+}
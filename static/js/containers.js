@@ -416,10 +416,21 @@ export class Deck extends Pawn {
                     = [this.data.contents[i], this.data.contents[j]];
             }
             this.#updateDeck();
+            this.#shuffled = true;
             this.dirty.add("selected");
             this.dirty.add("data");
         }
     }
+    // The server only reorders a deck when asked, it doesn't trust the contents we send
+    #shuffled = false;
+    serializeDirty() {
+        let out = super.serializeDirty();
+        if (this.#shuffled) {
+            out.shuffle = true;
+            this.#shuffled = false;
+        }
+        return out;
+    }
     
     flip() {
         if (this.data.back != null)
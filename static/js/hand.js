@@ -243,7 +243,7 @@ export default class Hand extends HTMLElement {
         imageElement.dataset.id = card.id;
         if (deck.flipped())
             imageElement.setAttribute("flipped", "");
-        imageElement.src = `${window.location.pathname}/assets/${card.data.contents[0]}`;
+        imageElement.src = window.manager.versionAssetUrl(`${window.location.pathname}/assets/${card.data.contents[0]}`);
         imageElement.style.borderRadius = `${deck.data.cornerRadius}in`;
         imageElement.style.aspectRatio = `${deck.data.size.x}/${deck.data.size.y}`;
 
@@ -261,6 +261,10 @@ export default class Hand extends HTMLElement {
 
                 imageElement.grabbed = false;
                 display.focus(); // Otherwise focus goes to <body> for some reason...
+
+                this.dispatchEvent(new CustomEvent("reorder", {
+                    detail: this.order()
+                }));
             }
             const cardMove = (e) => {
                 if (e.clientY < (window.innerHeight - 200)) {
@@ -312,12 +316,26 @@ export default class Hand extends HTMLElement {
                 card.data = serializedCard.data;
 
                 let imageElement = this.element.querySelector(`bird-card[data-id="${card.id}"]`);
-                imageElement.src = `${window.location.pathname}/assets/${card.data.contents[0]}`;
+                imageElement.src = window.manager.versionAssetUrl(`${window.location.pathname}/assets/${card.data.contents[0]}`);
                 imageElement.style.borderRadius = `${card.data.cornerRadius}in`;
                 imageElement.style.aspectRatio = `${card.data.size.x}/${card.data.size.y}`;
             }
         }
     }
+    order() {
+        return [...this.element.querySelectorAll('bird-card')].map(e => Number(e.dataset.id));
+    }
+    reorder(order) {
+        for (let id of order) {
+            let imageElement = this.element.querySelector(`bird-card[data-id="${id}"]`);
+            if (imageElement)
+                this.element.appendChild(imageElement);
+        }
+    }
+    removeCard(id) {
+        this.cards.delete(id);
+        this.element.querySelector(`bird-card[data-id="${id}"]`)?.remove();
+    }
     takeCard(id) {
         let card = this.cards.get(id);
         this.cards.delete(id);
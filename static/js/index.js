@@ -37,20 +37,13 @@ window.onload = () => {
 
     pluginLoader = new PluginLoader(manager);
 
-    manager.init((host) => {
-        // Games selection box
-        let games = [
-            ['Welcome', 'plugins/welcome.zip'],
-            ['Chess', 'plugins/chess.zip'],
-            ['Checkers', 'plugins/checkers.zip'],
-            ['Uno', 'plugins/uno.zip'],
-            ['Carcassonne', 'plugins/carcassonne.zip'],
-        ];
+    manager.init(async (host) => {
+        // Games selection box, from the plugins installed on the server
+        let games = await (await fetch(`catalog?v=${window.version}`)).json();
         games.forEach((g, i) => {
-            let name = g[0];
             let gameOption = document.createElement("option");
-            gameOption.value = name;
-            gameOption.innerText = name;
+            gameOption.value = g.id;
+            gameOption.innerText = g.name;
             document.querySelector("#games").appendChild(gameOption);
         });
         let gameOption = document.createElement("option");
@@ -59,10 +52,8 @@ window.onload = () => {
         gameOption.setAttribute("hidden", "");
         document.querySelector("#games").appendChild(gameOption);
 
-        async function loadGame(url) {
-            let blob = await ((await fetch(`${url}?v=${window.version}`)).blob());
-            let file = new File([blob], 'plugin.zip', { type: 'application/zip' });
-            await pluginLoader.loadFromFile(file, () => {
+        async function loadGame(id) {
+            await pluginLoader.loadFromLibrary(id, () => {
                 document.querySelector("#plugin-frame").src = window.location.href + "/page/";
                 document.querySelector("#plugin-frame").style.height = null;
             });
@@ -71,7 +62,7 @@ window.onload = () => {
             e.target.blur();
             
             e.target.setAttribute("disabled", "");
-            await loadGame(games[e.target.selectedIndex][1]);
+            await loadGame(e.target.value);
             e.target.removeAttribute("disabled");
         });
 
@@ -87,8 +78,12 @@ window.onload = () => {
             });
         });
         
-        if (host)
-            loadGame(games[0][1]);
+        // Prefer the welcome plugin, but a server might not install it
+        let initialGame = games.find(g => g.id == "welcome") ?? games[0];
+        if (host && initialGame) {
+            document.querySelector("#games").value = initialGame.id;
+            loadGame(initialGame.id);
+        }
         
         animate();
     });
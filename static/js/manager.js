@@ -6,7 +6,8 @@ import {
     Object3D,
     MeshPhongMaterial,
     DoubleSide,
-    TextureLoader
+    TextureLoader,
+    DefaultLoadingManager
 } from 'three';
 
 import Stats from 'three/addons/libs/stats.module.js';
@@ -184,18 +185,39 @@ export default class Manager extends EventTarget {
     };
     
     pawns = new Map();
+    joints = new Map(); // Joint id -> joint, attached by the server
+    groupSelection = new Set(); // Pawns ctrl-clicked to be grabbed together
+    groupHeld = false;
+    #groupPosition = new Vector3();
     host = false;
     id;
+    token; // Authenticates our HTTP requests, like plugin uploads
     users = new Map();
     info;
+    assetVersions = {}; // Uploaded asset paths -> versions, so their URLs are cached until they change
     
     static networkTimestep = 1000/20; // Milliseconds
     lastCallTime;
+    serverTick = 0; // Physics tick of the latest server update
     
     lastPingSent;
     
     constructor() {
         super();
+        DefaultLoadingManager.setURLModifier((url) => this.versionAssetUrl(url));
+    }
+
+    versionAssetUrl(url) {
+        let assetsPath = window.location.pathname + '/assets/';
+        let parsed = new URL(url, window.location.href);
+        if (!parsed.pathname.startsWith(assetsPath))
+            return url;
+
+        let version = this.assetVersions[decodeURIComponent(parsed.pathname.slice(assetsPath.length))];
+        if (version === undefined)
+            return url;
+        parsed.searchParams.set("v", version);
+        return parsed.href;
     }
 
     async init(callback) {
@@ -219,6 +241,13 @@ export default class Manager extends EventTarget {
                 content: e.detail
             });
         });
+        this.hand.addEventListener("reorder", (e) => {
+            this.sendSocket({
+                type: "reorder_hand",
+                from_id: this.id,
+                order: e.detail
+            });
+        });
         this.hand.addEventListener("take", (e) => {
             let card = e.detail;
 
@@ -309,6 +338,18 @@ export default class Manager extends EventTarget {
                     return;
                 }
             }
+            // Ctrl-click collects pawns, then grabbing any of them moves them all
+            if (e.ctrlKey || e.metaKey) {
+                if (!this.groupSelection.delete(toSelect[0].id))
+                    this.groupSelection.add(toSelect[0].id);
+            } else if (this.groupSelection.has(toSelect[0].id)) {
+                this.grabGroup(toSelect[0].id);
+            }
+            if (e.ctrlKey || e.metaKey || this.groupHeld) {
+                this.controls.saveState();
+                this.controls.reset();
+                return;
+            }
             if (e.button == 0) {
                 toSelect[0].grab(e.button, e.shiftKey);
                 this.controls.saveState();
@@ -316,7 +357,9 @@ export default class Manager extends EventTarget {
             }
         });
         display.addEventListener('pointerup', (e) => {
-            if (e.button == 0) {
+            if (e.button == 0 && this.groupHeld) {
+                this.releaseGroup();
+            } else if (e.button == 0) {
                 let selected = Array.from(this.pawns.values()).filter(p => p.selected);
                 for (let pawn of selected) {
                     pawn.release();
@@ -368,6 +411,9 @@ export default class Manager extends EventTarget {
             this.pawns.get(id).dispose();
             this.pawns.delete(id);
         });
+        this.joints.clear();
+        this.groupSelection.clear();
+        this.groupHeld = false;
         this.hand.clear();
         Cache.clear();
         Deck.textureCache.clear();
@@ -394,10 +440,39 @@ export default class Manager extends EventTarget {
             this.pawns.get(id).dispose();
             this.pawns.delete(id);
         }
+        this.groupSelection.delete(id);
     }
     sendRemovePawn(id) {
         this.sendSocket({ type:"remove_pawns", pawns:[id] });
     }
+    isAttached(id) {
+        return [...this.joints.values()].some(joint => joint.pawns.includes(id));
+    }
+    // The server moves grouped pawns, so we only send where the group is being dragged
+    groupPoint() {
+        return this.raycaster.intersectObject(this.plane)[0]?.point.add(new Vector3(0, 1, 0));
+    }
+    grabGroup(anchorId) {
+        let origin = this.groupPoint();
+        if (!origin)
+            return;
+        // The grabbed pawn goes first, so it's the one that snaps on release
+        let ids = [anchorId, ...[...this.groupSelection].filter(id => id != anchorId)];
+        this.sendSocket({ type:"grab_group", ids:ids, origin:origin.clone().setY(0) });
+        this.#groupPosition.copy(origin);
+        this.groupHeld = true;
+    }
+    releaseGroup() {
+        this.sendSocket({ type:"release_group" });
+        this.groupSelection.clear();
+        this.groupHeld = false;
+    }
+    sendAttachPawns(first, second, kind = "fixed") {
+        this.sendSocket({ type:"attach_pawns", joint:{ pawns:[first, second], kind:kind } });
+    }
+    sendDetachPawns(ids) {
+        this.sendSocket({ type:"detach_pawns", joints:ids });
+    }
     updatePawn(serializedPawn) {
         if (!this.pawns.has(serializedPawn.id)) {
             if (this.hand.cards.has(serializedPawn.id)) {
@@ -519,6 +594,13 @@ export default class Manager extends EventTarget {
             this.sendSocket({type: "update_pawns", pawns: to_update.map(p => p.serializeDirty())});
             to_update.forEach(p => p.dirty.clear());
         }
+        if (this.groupHeld) {
+            let position = this.groupPoint();
+            if (position && position.distanceToSquared(this.#groupPosition) > 0.0001) {
+                this.#groupPosition.copy(position);
+                this.sendSocket({ type:"move_group", position:position, rotation:new Quaternion() });
+            }
+        }
         if (this.localCursor.dirty) {
             this.sendUserStatus();
             this.localCursor.dirty = false;
@@ -858,7 +940,9 @@ export default class Manager extends EventTarget {
                 // We have initiated a connection
                 this.host = msg.host == msg.id;
                 this.id = msg.id;
+                this.token = msg.token;
                 this.info = msg.info;
+                this.assetVersions = msg.assets;
                 
                 // Start ticks
                 setInterval(() => this.tick(), Manager.networkTimestep);
@@ -866,6 +950,7 @@ export default class Manager extends EventTarget {
                     let pawn = deserializePawn(p);
                     this.addPawn(pawn);
                 });
+                msg.joints.forEach(j => this.joints.set(j.id, j));
 
                 // Start ping tester
                 // Can't use WebSocket ping event type because it's not available for browser use
@@ -908,6 +993,7 @@ export default class Manager extends EventTarget {
             } else if (type == "assign_host") {
                 assignHost(msg.id);
             } else if (type == "register_game") {
+                this.assetVersions = msg.assets;
                 this.info = msg;
                 delete this.info.type;
             } else if (type == "register_pawn") {
@@ -922,14 +1008,27 @@ export default class Manager extends EventTarget {
             } else if (type == "remove_pawns") {
                 msg.pawns.forEach(id => this.removePawn(id));
             } else if (type == "update_pawns") {
+                if (msg.tick !== undefined)
+                    this.serverTick = Math.max(this.serverTick, msg.tick);
                 msg.pawns.forEach(p => this.updatePawn(p));
                 // if (msg.collisions)
                 //     console.log(msg.collisions);
+            } else if (type == "attach_pawns") {
+                this.joints.set(msg.joint.id, msg.joint);
+            } else if (type == "detach_pawns") {
+                msg.joints.forEach(id => this.joints.delete(id));
             } else if (type == "clear_pawns") {
                 this.clearPawns();
             } else if (type == "add_pawn_to_hand") {
                 if (!this.hand.cards.has(msg.pawn.id))
                     this.hand.pushCard(deserializePawn(msg.pawn), false);
+            } else if (type == "remove_pawns_from_hand") {
+                msg.pawns.forEach(id => this.hand.removeCard(id));
+            } else if (type == "reorder_hand") {
+                this.hand.reorder(msg.order);
+            } else if (type == "show_pawn") {
+                let card = msg.pawn.data.contents?.[0] ?? msg.pawn.name;
+                this.chat.addChatEntry(`revealed ${card?.split('/').pop()}`, this.users.get(msg.from_id)?.color);
             } else if (type == "hand_count") {
                 //this.users.get(msg.id).cardTextElement.innerText = `[${msg.count} card${msg.count == 1 ? '' : 's'}]`;
                 this.users.get(msg.id).updateCardCount(msg.count);
@@ -37,6 +37,7 @@ export class Pawn {
     texture;
     
     moveable = true;
+    physics = {}; // Only used by the server
     
     // Non-Serialized
     #lastPosition = new Vector3();
@@ -63,7 +64,7 @@ export class Pawn {
     constructor({
         position = new Vector3(), rotation = new Quaternion(), selectRotation = new Quaternion(),
         mesh = null, tint = 0xffffff, texture = null,
-        moveable = true, id = null, name = null
+        moveable = true, physics = {}, id = null, name = null
     }) {
         this.id = (id == null) ? Pawn.nextId() : id;
         
@@ -73,6 +74,7 @@ export class Pawn {
 
         this.name = name;
         this.moveable = moveable;
+        this.physics = physics;
         this.mesh = mesh;
         this.tint = tint;
         this.texture = texture;
@@ -358,8 +360,9 @@ export class Pawn {
                         if (child == hits[0].object)
                             isParent = true;
                     });
-                    // Don't merge with selected pawns
-                    if (isParent && !rhs.networkSelected && !rhs.selected) {
+                    // Don't merge with selected pawns, or anything attached by a joint
+                    if (isParent && !rhs.networkSelected && !rhs.selected
+                        && !window.manager.isAttached(this.id) && !window.manager.isAttached(rhs.id)) {
                         rhs.merge(this);
                         break;
                     }
@@ -516,6 +519,20 @@ export class SnapPoint extends Pawn {
     static className() { return "SnapPoint"; };
 }
 
+export class HiddenZone extends Pawn {
+    data = {
+        size: new Vector3(),
+    }
+
+    constructor({size=new Vector3(1,1,1), ...rest}) {
+        rest.moveable = false;
+        super(rest);
+        this.data.size = size;
+    }
+
+    static className() { return "HiddenZone"; };
+}
+
 export class Dice extends Pawn {
     data = {
         rollRotations: []
@@ -1,7 +1,7 @@
-import { Pawn, SnapPoint, Dice } from './pawn';
+import { Pawn, SnapPoint, HiddenZone, Dice } from './pawn';
 import { Deck, Container } from './containers';
 
-export { Pawn, SnapPoint, Dice } from './pawn';
+export { Pawn, SnapPoint, HiddenZone, Dice } from './pawn';
 export { Deck, Container } from './containers';
 
 export function deserializePawn(serializedPawn) {
@@ -13,6 +13,9 @@ export function deserializePawn(serializedPawn) {
         case "SnapPoint":
             result = SnapPoint.deserialize(serializedPawn);
             break;
+        case "HiddenZone":
+            result = HiddenZone.deserialize(serializedPawn);
+            break;
         case "Dice":
             result = Dice.deserialize(serializedPawn);
             break;
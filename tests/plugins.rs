@@ -0,0 +1,379 @@
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::Cursor;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use bg3d::events::Event;
+use bg3d::harness::Harness;
+use bg3d::library::PluginLibrary;
+use bg3d::lobby::{Lobby, MAX_ASSET_SIZE};
+use bg3d::plugin::{check_asset, Plugin};
+use bg3d::pawn::{Pawn, PawnData, PawnId};
+use bg3d::server::{Server, MAX_UPLOAD_SIZE};
+use bg3d::user::UserId;
+use serde_json::json;
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+
+// Host a lobby with `plugin` loaded, and let everything settle onto the table
+fn start(plugin: &str) -> (Harness, UserId) {
+    let mut harness = Harness::new();
+    let host = harness.connect().unwrap();
+    harness.load_plugin(host, format!("plugins/{}", plugin)).unwrap();
+    harness.step(90).unwrap();
+
+    let errors: Vec<String> = harness.chat(host).into_iter().filter(|c| c.contains("error") || c.contains("Failed")).collect();
+    assert!(errors.is_empty(), "{} reported errors: {:?}", plugin, errors);
+    (harness, host)
+}
+fn deck_contents(pawn: &Pawn) -> &Vec<String> {
+    match &pawn.data {
+        PawnData::Deck { contents, .. } => contents,
+        _ => panic!("{:?} isn't a deck", pawn.name),
+    }
+}
+fn assert_on_table(harness: &Harness) {
+    for pawn in harness.pawns() {
+        assert!(pawn.position.y > -1., "{:?} fell through the table", pawn.name);
+    }
+}
+
+#[test]
+fn chess_sets_up_board() {
+    let (harness, _) = start("chess");
+
+    assert_eq!(harness.pawns_named("Board").count(), 1);
+    assert_eq!(harness.pawns_named("king").count(), 2);
+    assert_eq!(harness.pawns_named("queen").count(), 2);
+    assert_eq!(harness.pawns_named("pawn").count(), 16);
+    assert_eq!(harness.pawns().filter(|p| p.mesh.as_deref().is_some_and(|m| m.starts_with("chess/"))).count(), 32);
+    assert_on_table(&harness);
+}
+
+#[test]
+fn checkers_pieces_rest_on_board() {
+    let (harness, _) = start("checkers");
+
+    assert_eq!(harness.pawns_named("red").count(), 12);
+    assert_eq!(harness.pawns_named("black").count(), 12);
+    for checker in harness.pawns_named("red").chain(harness.pawns_named("black")) {
+        assert!(checker.position.y > 0. && checker.position.y < 3., "{:?} isn't resting on the board", checker.position);
+        assert!(checker.position.x.abs() < 8. && checker.position.z.abs() < 8.);
+    }
+}
+
+#[test]
+fn uno_deals_and_passes_cards() {
+    let (mut harness, host) = start("uno");
+    let player = harness.connect().unwrap();
+
+    let deck = harness.pawns_named("Uno").next().unwrap();
+    let deck_id = deck.id;
+    assert_eq!(deck_contents(deck).len(), 60);
+
+    // Draw two cards into the host's hand
+    for new_id in [1000, 1001] {
+        harness.send_json(host, json!({
+            "type": "extract_pawns", "from_id": deck_id, "new_id": new_id, "into_id": host
+        })).unwrap();
+    }
+    assert_eq!(harness.hand(host).len(), 2);
+    assert_eq!(deck_contents(&harness.lobby.pawns[&deck_id]).len(), 58);
+    assert!(harness.received(host).iter().any(|e| e["type"] == "add_pawn_to_hand"));
+
+    // Pass one on, then discard the other back onto the deck
+    harness.send_json(host, json!({
+        "type": "pass_pawn", "from_id": host, "target_id": 1000, "to_id": player
+    })).unwrap();
+    assert_eq!(harness.hand(player).keys().collect::<Vec<_>>(), vec![&PawnId(1000)]);
+
+    harness.send_json(host, json!({
+        "type": "discard_pawn", "from_id": host, "target_id": 1001, "into_id": deck_id
+    })).unwrap();
+    assert!(harness.hand(host).is_empty());
+    assert_eq!(deck_contents(&harness.lobby.pawns[&deck_id]).len(), 59);
+
+    // Players can't play from someone else's hand
+    assert!(harness.send_json(host, json!({
+        "type": "take_pawn", "from_id": player, "target_id": 1000
+    })).is_err());
+}
+
+#[test]
+fn carcassonne_tiles_and_meeples() {
+    let (mut harness, host) = start("carcassonne");
+
+    let tiles: Vec<&Pawn> = harness.pawns_named("Landscape Tiles").collect();
+    let mut counts: Vec<usize> = tiles.iter().map(|t| deck_contents(t).len()).collect();
+    counts.sort();
+    assert_eq!(counts, vec![1, 72]);
+
+    // Take a meeple out of a bag, it drops onto the table
+    let bag = harness.pawns_named("Blue Meeple").next().unwrap().id;
+    harness.send_json(host, json!({
+        "type": "extract_pawns", "from_id": bag, "new_id": 1000
+    })).unwrap();
+    harness.step(90).unwrap();
+
+    let meeple = &harness.lobby.pawns[&PawnId(1000)];
+    assert_eq!(meeple.name.as_deref(), Some("Blue Meeple"));
+    assert!(matches!(harness.lobby.pawns[&bag].data, PawnData::Container { capacity: Some(7), .. }));
+    assert_on_table(&harness);
+}
+
+#[test]
+fn chat_reaches_other_users() {
+    let (mut harness, host) = start("chess");
+    let player = harness.connect().unwrap();
+
+    harness.send_json(player, json!({ "type": "chat", "content": "gg" })).unwrap();
+    assert!(harness.chat(host).contains(&"gg".to_string()));
+}
+
+#[test]
+fn detaching_only_reports_known_joints() {
+    let (mut harness, host) = start("checkers");
+    let player = harness.connect().unwrap();
+    let pawns: Vec<u64> = harness.pawns().filter(|p| p.moveable).take(2).map(|p| p.id.0).collect();
+    harness.send_json(host, json!({ "type": "attach_pawns", "joint": { "pawns": pawns, "kind": "fixed" } })).unwrap();
+    let joint = harness.received(player).iter().rev()
+        .find(|m| m["type"] == "attach_pawns").unwrap()["joint"]["id"].clone();
+
+    harness.send_json(host, json!({ "type": "detach_pawns", "joints": [joint, 9999] })).unwrap();
+    harness.send_json(host, json!({ "type": "detach_pawns", "joints": [9999] })).unwrap();
+    let detached: Vec<&serde_json::Value> = harness.received(player).iter().filter(|m| m["type"] == "detach_pawns").collect();
+    assert_eq!(detached.len(), 1);
+    assert_eq!(detached[0]["joints"], json!([joint]));
+}
+
+#[test]
+fn library_plugins_start_by_id() {
+    let mut harness = Harness::new();
+    harness.lobby.library = Arc::new(PluginLibrary::scan("plugins"));
+    let host = harness.connect().unwrap();
+    let player = harness.connect().unwrap();
+
+    assert!(harness.send_json(player, json!({ "type": "register_plugin", "id": "chess" })).is_err());
+    assert!(harness.send_json(host, json!({ "type": "register_plugin", "id": "missing" })).is_err());
+
+    harness.send_json(host, json!({ "type": "register_plugin", "id": "chess" })).unwrap();
+    harness.step(90).unwrap();
+    assert_eq!(harness.pawns_named("king").count(), 2);
+    assert!(harness.lobby.assets.contains_key("/main.lua"));
+    assert!(harness.received(player).iter().any(|m| m["type"] == "register_game"));
+}
+
+#[test]
+fn library_indexes_plugin_folders() {
+    let dir = std::env::temp_dir().join(format!("bg3d-library-{}", std::process::id()));
+    fs::create_dir_all(dir.join("checkers")).unwrap();
+    fs::create_dir_all(dir.join("notes")).unwrap();
+    for file in ["main.lua", "manifest.json"] {
+        fs::copy(format!("plugins/checkers/{}", file), dir.join("checkers").join(file)).unwrap();
+    }
+
+    let library = PluginLibrary::scan(&dir);
+    let ids: Vec<String> = library.catalog().into_iter().map(|e| e.id).collect();
+    assert_eq!(ids, ["checkers"]);
+    assert!(library.load("checkers").unwrap().files.contains_key("/main.lua"));
+
+    // A packed zip of the same name is preferred
+    Plugin::from_folder("plugins/chess").unwrap().write_zip(File::create(dir.join("checkers.zip")).unwrap()).unwrap();
+    let library = PluginLibrary::scan(&dir);
+    assert_eq!(library.load("checkers").unwrap().info.name, "Chess");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Serve the harness' lobby as "/test", to request its routes without a socket
+fn serve(harness: &mut Harness) -> (Router, Arc<Mutex<Lobby>>) {
+    let server = Server::default();
+    let lobby = Arc::new(Mutex::new(std::mem::replace(&mut harness.lobby, Lobby::new())));
+    server.lobbies.try_write().unwrap().insert("test".to_string(), lobby.clone());
+    (Router::new().nest("/:lobby", server.lobby_router()), lobby)
+}
+async fn upload(router: &Router, token: Option<&str>, zip: Vec<u8>) -> StatusCode {
+    let mut request = Request::post("/test/plugin");
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    router.clone().oneshot(request.body(Body::from(zip)).unwrap()).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn plugin_uploads_need_the_host_token() {
+    let mut harness = Harness::new();
+    let host = harness.connect().unwrap();
+    let player = harness.connect().unwrap();
+    let host_token = harness.lobby.users[&host].token.clone();
+    let player_token = harness.lobby.users[&player].token.clone();
+    let (router, lobby) = serve(&mut harness);
+
+    let mut zip = Cursor::new(vec![]);
+    Plugin::from_folder("plugins/checkers").unwrap().write_zip(&mut zip).unwrap();
+    let zip = zip.into_inner();
+
+    assert_eq!(upload(&router, None, zip.clone()).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(upload(&router, Some("wrong"), zip.clone()).await, StatusCode::FORBIDDEN);
+    assert_eq!(upload(&router, Some(&player_token), zip.clone()).await, StatusCode::FORBIDDEN);
+    assert_eq!(upload(&router, Some(&host_token), vec![0; MAX_UPLOAD_SIZE + 1]).await, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(lobby.lock().await.info.is_none());
+
+    assert_eq!(upload(&router, Some(&host_token), zip).await, StatusCode::NO_CONTENT);
+    assert!(lobby.lock().await.assets.contains_key("/main.lua"));
+}
+
+#[tokio::test]
+async fn uploaded_assets_are_served_whole() {
+    let mut harness = Harness::new();
+    let host = harness.connect().unwrap();
+    let mut plugin = Plugin::from_folder("plugins/checkers").unwrap();
+    let script: Vec<u8> = (0..200_000).map(|i| b'a' + (i % 26) as u8).collect();
+    plugin.files.insert("/long.lua".to_string(), script.clone());
+    harness.lobby.install_plugin(host, plugin).unwrap();
+    let (router, _lobby) = serve(&mut harness);
+
+    let request = Request::get("/test/assets/long.lua").body(Body::empty()).unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], script.len().to_string());
+    assert_eq!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap(), script);
+}
+
+#[test]
+fn zipped_plugins_install_within_limits() {
+    let mut zip = Cursor::new(vec![]);
+    Plugin::from_folder("plugins/checkers").unwrap().write_zip(&mut zip).unwrap();
+    zip.set_position(0);
+
+    let mut harness = Harness::new();
+    let host = harness.connect().unwrap();
+    let player = harness.connect().unwrap();
+    let plugin = Plugin::from_zip(zip).unwrap();
+    assert!(harness.lobby.install_plugin(player, Plugin::from_folder("plugins/checkers").unwrap()).is_err());
+    harness.lobby.install_plugin(host, plugin).unwrap();
+    harness.step(90).unwrap();
+    assert!(harness.pawns().count() > 0);
+
+    // Sizes are checked while inflating, not taken from the zip headers
+    let mut plugin = Plugin::from_folder("plugins/checkers").unwrap();
+    plugin.files.insert("/huge.png".to_string(), vec![0; MAX_ASSET_SIZE + 1]);
+    let mut zip = Cursor::new(vec![]);
+    plugin.write_zip(&mut zip).unwrap();
+    zip.set_position(0);
+    assert!(Plugin::from_zip(zip).is_err());
+
+    // Nor when the headers claim the asset is tiny
+    let mut zip = Cursor::new(vec![]);
+    plugin.write_zip(&mut zip).unwrap();
+    let (real, claimed) = ((MAX_ASSET_SIZE as u32 + 1).to_le_bytes(), 16u32.to_le_bytes());
+    let mut data = zip.into_inner();
+    let mut patched = 0;
+    for i in 0..data.len() - 4 {
+        if data[i..i + 4] == real {
+            data[i..i + 4].copy_from_slice(&claimed);
+            patched += 1;
+        }
+    }
+    assert_eq!(patched, 2); // Local and central directory headers
+    let Err(error) = Plugin::from_zip(Cursor::new(data)) else { panic!("Understated asset was accepted") };
+    assert!(error.to_string().contains("over"), "{}", error);
+}
+
+#[test]
+fn lobbies_share_identical_assets() {
+    let library = Arc::new(PluginLibrary::scan("plugins"));
+    let mut lobbies: Vec<(Harness, UserId)> = (0..2).map(|_| {
+        let mut harness = Harness::new();
+        harness.lobby.library = library.clone();
+        let host = harness.connect().unwrap();
+        harness.send_json(host, json!({ "type": "register_plugin", "id": "uno" })).unwrap();
+        (harness, host)
+    }).collect();
+
+    let (a, b) = (&lobbies[0].0.lobby.assets, &lobbies[1].0.lobby.assets);
+    assert!(a.keys().all(|path| Arc::ptr_eq(&a[path].data, &b[path].data)));
+
+    // Clients are sent the versions to request assets with
+    let version = a["/main.lua"].version();
+    let (harness, host) = &mut lobbies[0];
+    let register = harness.received(*host).iter().find(|m| m["type"] == "register_game").unwrap().clone();
+    assert_eq!(register["assets"]["main.lua"], version);
+}
+
+#[test]
+fn plugin_checks_catch_manifest_mistakes() {
+    let mut plugin = Plugin::from_folder("plugins/checkers").unwrap();
+    assert!(plugin.problems().is_empty(), "{:?}", plugin.problems());
+
+    plugin.info.name = String::new();
+    plugin.files.insert("/manifest.json".to_string(), br#"{ "name": "", "description": "", "author": "", "rotation_increment": 1 }"#.to_vec());
+    plugin.files.remove("/main.lua");
+    let problems = plugin.problems();
+    assert!(problems.iter().any(|p| p.contains("empty \"name\"")));
+    assert!(problems.iter().any(|p| p.contains("unknown field \"rotation_increment\"")));
+    assert!(problems.iter().any(|p| p.contains("main.lua")));
+}
+
+#[test]
+fn spoofed_uploads_are_rejected() {
+    let plugin = Plugin::from_folder("plugins/checkers").unwrap();
+    let spoofs = [
+        ("/page.html", "data:text/html,<script>alert(1)</script>"), // Not an allowed type
+        ("/card.png", "data:text/html,<script>alert(1)</script>"), // Declared type doesn't match
+        ("/card.png", "data:image/png,<script>alert(1)</script>"), // Not a PNG
+        ("/card.svg", "data:image/svg+xml,<html><svg></svg><script>alert(1)</script></html>"),
+        ("/card.svg", "data:image/svg+xml,<svg xmlns='http://www.w3.org/2000/svg' onload='alert(1)'/>"),
+        ("/card.svg", "data:image/svg+xml,<svg xmlns='http://www.w3.org/2000/svg'><script>alert(1)</script></svg>"),
+        ("/card.svg", "data:image/svg+xml,<svg xmlns='http://www.w3.org/2000/svg'><a href='javascript:alert(1)'><rect/></a></svg>"),
+        ("/rules.json", "data:application/json,<script>alert(1)</script>"),
+        ("/main.lua", "data:text/x-lua,<html><script>alert(1)</script></html>"),
+    ];
+    for (path, data_url) in spoofs {
+        let mut harness = Harness::new();
+        let host = harness.connect().unwrap();
+        let mut assets = plugin.data_urls();
+        assets.insert(path.to_string(), data_url.to_string());
+
+        let result = harness.send(host, Event::RegisterGame { info: Cow::Borrowed(&plugin.info), assets });
+        assert!(result.is_err(), "{} ({}) was accepted", path, data_url);
+        assert!(harness.lobby.assets.is_empty());
+    }
+    // Sniffed as XML, so namespaces, entities and animations can't sneak a script past
+    let svg = |body: &str| format!("<svg xmlns='http://www.w3.org/2000/svg' xmlns:xlink='http://www.w3.org/1999/xlink'>{}</svg>", body);
+    let bypasses = [
+        "<svg:script xmlns:svg='http://www.w3.org/2000/svg'>alert(1)</svg:script>",
+        "<x:script xmlns:x='http://www.w3.org/2000/svg'>alert(1)</x:script>",
+        "<use href='jav&#x61;script:alert(1)'/>",
+        "<use xlink:href=' JavaScript:alert(1)'/>",
+        "<set attributeName='href' to='javascript:alert(1)'/>",
+        "<animate attributeName='href' values='javascript:alert(1)'/>",
+        "<foreignObject><body xmlns='http://www.w3.org/1999/xhtml'><script>alert(1)</script></body></foreignObject>",
+        "<metadata><h:script xmlns:h='http://www.w3.org/1999/xhtml'>alert(1)</h:script></metadata>",
+        "<rect ONCLICK='alert(1)'/>",
+    ];
+    assert!(check_asset("/card.svg", None, svg("<rect width='1' height='1'/>").as_bytes()).is_ok());
+    for bypass in bypasses {
+        assert!(check_asset("/card.svg", None, svg(bypass).as_bytes()).is_err(), "{} was accepted", bypass);
+    }
+    let borders = [
+        "static/games/generic/circle.svg", "static/games/generic/hex.svg", "static/games/generic/welcome.svg",
+        "static/games/notes/mat.svg", "static/games/poker/mat.svg", "static/games/go/board.svg",
+        "plugins/catan/harbors/harbor.svg", "plugins/box.svg",
+    ];
+    for border in borders {
+        assert!(check_asset(border, None, &fs::read(border).unwrap()).is_ok(), "{} was rejected", border);
+    }
+
+    // Zipped uploads are sniffed too
+    let mut plugin = Plugin::from_folder("plugins/checkers").unwrap();
+    plugin.files.insert("/card.png".to_string(), b"<script>alert(1)</script>".to_vec());
+    let mut zip = Cursor::new(vec![]);
+    plugin.write_zip(&mut zip).unwrap();
+    zip.set_position(0);
+    assert!(Plugin::from_zip(zip).is_err());
+    assert!(plugin.problems().iter().any(|p| p.contains("/card.png")));
+}